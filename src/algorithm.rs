@@ -1,134 +1,804 @@
-use std::f32::consts::{FRAC_PI_4,FRAC_PI_2};
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+use std::fmt::Debug;
 
-use crate::model::{Car, Position, Road, RoadTurnDirection};
-use crate::physics::{MAX_STEER, STEER_SPEED};
+use crate::model::{Car, CarDebugInfo, Gear, NearbyCar, Position, Road, VehicleModel, VehicleParams};
+use crate::navigator::{ConflictZone, Navigator, Priority, RoadIndex};
+use crate::reservations::Slot;
+use crate::signals::{LightState, Signal};
 use crate::util::normalize_angle;
 
+/// Longest piece of road car may spend turning the steering wheel from
+/// curvature of one road to curvature of the next one.
+const MAX_STEERING_TRANSITION: f32 = 60.0;
+/// Turns further than that do not affect the speed
+const SPEED_PLANNING_DISTANCE: f32 = 600.0;
+/// Distance left between bumpers when car stops behind another one
+const MIN_GAP: f32 = 10.0;
+/// Part of the tire grip speed planner lets the car use in turns, the rest is kept for corrections
+const LATERAL_GRIP_MARGIN: f32 = 0.8;
+/// How much faster than planned car may go before it uses brakes
+const BRAKING_TOLERANCE: f32 = 2.0;
+/// Seconds of driving car following keeps between itself and the car in front
+const TIME_HEADWAY: f32 = 1.5;
+/// How quickly car following stops accelerating as it gets close to the planned speed
+const FREE_ROAD_EXPONENT: i32 = 4;
+/// How far ahead controllers look for changes of the road curvature, unless told otherwise
+pub const DEFAULT_LOOKAHEAD: f32 = 300.0;
+/// Car facing further than that away from the road backs up to turn around
+const REVERSE_ANGLE: f32 = FRAC_PI_2;
+/// Backing up car drives forwards again once it faces the road at least that well
+const FORWARD_ANGLE: f32 = FRAC_PI_4;
+/// Speed of backing up, it is only done to turn around
+const REVERSE_SPEED: f32 = 40.0;
+/// Seconds of driving a car changing lanes wants in front of it, and leaves to the car behind it, on the new lane
+const LANE_CHANGE_HEADWAY: f32 = 0.8;
+/// Car overtakes a leader that is closer than that and notably slower than it would like to go
+const OVERTAKING_DISTANCE: f32 = 150.0;
+const OVERTAKING_SPEED_GAIN: f32 = 20.0;
+/// Next lane change starts once the car is that part of the lane width away from the center of its lane
+const LANE_CHANGE_SETTLED: f32 = 0.25;
+/// Car gives way to the cars with the right of way that get to the merge sooner than that, in seconds
+const GIVE_WAY_HEADWAY: f32 = 2.5;
+/// Car only looks out for the cars it may have to give way to once it is that close to the merge,
+/// and only looks that far back along the other roads
+const GIVE_WAY_DISTANCE: f32 = 400.0;
+/// Car at a stop sign has stopped once it is that much closer to the line than `MIN_GAP`
+const STOP_SIGN_TOLERANCE: f32 = 5.0;
+/// Arrival times to a merge or to an intersection slot are counted as if stopped cars drove that fast, so that they still compare
+pub(crate) const CREEP_SPEED: f32 = 10.0;
+
 pub trait Thinker {
-    fn think(&mut self);
+    /// `nearby_cars` are all other cars which are close enough to matter,
+    /// `signals` are the traffic lights at the ends of the roads
+    fn think(&mut self, nearby_cars: &[NearbyCar], signals: &[Signal]);
+}
+
+/// What a controller knows about its car and the world around it
+pub struct Observation<'a> {
+    pub position: &'a Position,
+    /// negative when the car moves backwards
+    pub speed: f32,
+    pub steer: f32,
+    pub lateral_speed: f32,
+    pub yaw_rate: f32,
+    pub gear: Gear,
+    pub cruise_speed: f32,
+    pub params: &'a VehicleParams,
+    pub vehicle_model: &'a VehicleModel,
+    /// already tracked to the current position of the car
+    pub navigator: &'a Navigator,
+    pub nearby_cars: &'a [NearbyCar],
+    pub signals: &'a [Signal],
+    pub slot: Option<&'a Slot>,
+}
+
+/// What a controller wants the car to do, see the fields of `Car` with the same names
+#[derive(Debug,Clone)]
+pub struct Controls {
+    pub desired_speed: f32,
+    pub desired_steer: f32,
+    pub brakes: bool,
+    pub desired_gear: Gear,
+}
+
+/// Algorithm driving a car. Every car has its own, so it may remember things between ticks.
+/// Car that faces away from its road backs up to turn around by itself, controllers only drive forward.
+pub trait Controller: Debug {
+    /// `debug` is there to show what the controller was thinking about
+    fn control(&mut self, observation: &Observation, debug: &mut CarDebugInfo) -> Controls;
 }
 
 impl Thinker for Car {
 
-    fn think(&mut self) {
-        let road = self.navigator.get_road();
-        self.brakes = false;
+    fn think(&mut self, nearby_cars: &[NearbyCar], signals: &[Signal]) {
+        // find the closest position on the road, moving on to the next road if this one is over
+        self.navigator.track(self.position.coordinates);
+        // controllers steer over to the new lane just like they rejoin any road they are off
+        if let Some(lane) = plan_lane_change(&self.observe(nearby_cars, signals)) {
+            self.navigator.change_lane(lane);
+            self.navigator.track(self.position.coordinates);
+        }
 
-        // find the closest position on the road
-        let DesiredPosition { position: desired_position, on_road_end } = get_desired_position_on_a_road(&self.position, road);
+        // controller and its debug info are borrowed apart from the rest of the car, which the controller looks at
+        let Car { controller, debug, position, speed, steer, lateral_speed, yaw_rate, gear, desired_gear, cruise_speed, params, vehicle_model, navigator, slot, .. } = self;
+        let observation = Observation {
+            position, speed: *speed, steer: *steer, lateral_speed: *lateral_speed, yaw_rate: *yaw_rate, gear: *gear, cruise_speed: *cruise_speed,
+            params, vehicle_model, navigator, nearby_cars, signals, slot: slot.as_ref(),
+        };
+        // backing up is the same for every controller, they are only asked to drive forward
+        let controls = match turn_around(&observation, *desired_gear == Gear::Reverse, debug) {
+            Some(controls) => controls,
+            None => controller.control(&observation, debug),
+        };
 
-        let distance_to_desired_position = ((self.position.coordinates.0-desired_position.coordinates.0).powi(2) +
-                                            (self.position.coordinates.1-desired_position.coordinates.1).powi(2)).sqrt();
+        self.desired_speed = controls.desired_speed;
+        self.desired_steer = controls.desired_steer;
+        self.brakes = controls.brakes;
+        self.desired_gear = controls.desired_gear;
+    }
+}
+
+impl Car {
+    /// What a controller of this car would see
+    pub fn observe<'a>(&'a self, nearby_cars: &'a [NearbyCar], signals: &'a [Signal]) -> Observation<'a> {
+        Observation {
+            position: &self.position,
+            speed: self.speed,
+            steer: self.steer,
+            lateral_speed: self.lateral_speed,
+            yaw_rate: self.yaw_rate,
+            gear: self.gear,
+            cruise_speed: self.cruise_speed,
+            params: &self.params,
+            vehicle_model: &self.vehicle_model,
+            navigator: &self.navigator,
+            nearby_cars,
+            signals,
+            slot: self.slot.as_ref(),
+        }
+    }
+}
 
-        // vector points from current self coordinates to closest pont on the road
-        let angle_to_desired_position = (-self.position.coordinates.1+desired_position.coordinates.1)
-                                  .atan2(-self.position.coordinates.0+desired_position.coordinates.0);
+/// Steers by blending the direction towards the road with the direction of the road,
+/// the closer the car is to the road the more it follows road's direction.
+#[derive(Debug)]
+pub struct Heuristic {
+    /// distance ahead in which changes of the road curvature are taken into account
+    pub lookahead: f32,
+}
+
+impl Default for Heuristic {
+    fn default() -> Self {
+        Self { lookahead: DEFAULT_LOOKAHEAD }
+    }
+}
+
+impl Controller for Heuristic {
+
+    fn control(&mut self, car: &Observation, debug: &mut CarDebugInfo) -> Controls {
+        let road = car.navigator.get_road();
+        let progress = car.navigator.get_progress();
+        let desired_position = road.get_position_at(progress);
+
+        let distance_to_desired_position = ((car.position.coordinates.0-desired_position.coordinates.0).powi(2) +
+                                            (car.position.coordinates.1-desired_position.coordinates.1).powi(2)).sqrt();
+
+        // vector points from current car coordinates to closest pont on the road
+        let angle_to_desired_position = (-car.position.coordinates.1+desired_position.coordinates.1)
+                                  .atan2(-car.position.coordinates.0+desired_position.coordinates.0);
 
         // how should car hold the steering when it's precisely on the road.
         // Depends on:
         // - form of the road (primary)
         // - form of next part of road
         // - distance to next part of road
-        let taget_steering = get_taget_steering(self, road);
+        let taget_steering = get_taget_steering(car, self.lookahead);
 
         // this relative angle points towards clothest point on a road
-        let angle_to_position = normalize_angle(angle_to_desired_position - self.position.orientation);
+        let angle_to_position = normalize_angle(angle_to_desired_position - car.position.orientation);
 
         // this relative angle is absolute target steering
-        let angle_to_orientation = normalize_angle(desired_position.orientation - self.position.orientation); 
+        let angle_to_orientation = normalize_angle(desired_position.orientation - car.position.orientation); 
+
+        debug.desired_position = Some(desired_position);
+        debug.angle_to_position = Some(angle_to_position);
+        debug.angle_to_orientation = Some(angle_to_orientation);
+        debug.curvature_profile = Some(get_curvature_profile(car, self.lookahead));
 
         // car ai chooses between two angles
-        let orientation_bias = get_orientation_bias(distance_to_desired_position);
+        let orientation_bias = get_orientation_bias(distance_to_desired_position, car.params.max_steer);
         let turning_angle = angle_to_position * (1.0 - orientation_bias)  +  angle_to_orientation * (orientation_bias);
+        debug.turning_angle = Some(turning_angle);
+
+        let desired_steer = turning_angle.abs().min(FRAC_PI_4) / FRAC_PI_4 * car.params.max_steer * turning_angle.signum() + taget_steering * orientation_bias ;
+        follow_planned_speed(car, Gear::Drive, get_planned_speed(car, road, progress), desired_steer, debug)
+    }
+}
+
+/// Controls that back the car up with the wheels turned away from the road while it faces away from it,
+/// like in a three-point turn. `None` once the car may drive forward, which `reversing` car only does
+/// when it faces the road well enough.
+fn turn_around(car: &Observation, reversing: bool, debug: &mut CarDebugInfo) -> Option<Controls> {
+    let desired_position = car.navigator.get_road().get_position_at(car.navigator.get_progress());
+    let angle_to_orientation = normalize_angle(desired_position.orientation - car.position.orientation);
+    let max_angle = if reversing { FORWARD_ANGLE } else { REVERSE_ANGLE };
+    if angle_to_orientation.abs() <= max_angle { return None; }
+
+    *debug = CarDebugInfo { desired_position: Some(desired_position), angle_to_orientation: Some(angle_to_orientation), ..Default::default() };
+    // backing up with the wheels turned away from the road swings the nose towards it
+    let desired_steer = -car.params.max_steer * angle_to_orientation.signum();
+    Some(follow_planned_speed(car, Gear::Reverse, REVERSE_SPEED, desired_steer, debug))
+}
+
+/// Controls that keep `speed_limit`, the distance to nearby cars and stop lines in `gear`.
+/// Every controller shares this, they only differ in steering.
+pub(crate) fn follow_planned_speed(car: &Observation, gear: Gear, speed_limit: f32, desired_steer: f32, debug: &mut CarDebugInfo) -> Controls {
+    let mut planned_speed = speed_limit.min(get_speed_limit_for_nearby_cars(car, gear)).min(get_speed_limit_for_slot(car));
+    // releasing the pedal is not enough to keep up with the plan
+    let mut brakes = car.speed.abs() > planned_speed + BRAKING_TOLERANCE;
+
+    // stop line is followed just like a car standing on it
+    let leader = if gear == Gear::Drive {
+        [find_leader(car), find_stop_line(car)].into_iter().flatten().min_by(|a, b| a.distance.total_cmp(&b.distance))
+    } else { None };
+    debug.leader_distance = leader.as_ref().map(|leader| leader.distance);
+    debug.gap = leader.as_ref().map(|leader| leader.gap);
+    debug.approach_rate = leader.as_ref().map(|leader| car.speed - leader.speed);
+    if let Some(leader) = leader {
+        planned_speed = planned_speed.min(get_following_speed(car, speed_limit, &leader));
+        // engine alone does not slow down the car as fast as car following wants
+        brakes |= get_following_acceleration(car, speed_limit, &leader, car.speed) < -car.params.acceleration;
+    }
+
+    debug.planned_speed = Some(planned_speed);
+    Controls {
+        desired_speed: planned_speed,
+        desired_steer,
+        brakes,
+        desired_gear: gear,
+    }
+}
 
-        self.desired_speed = 170.;
-        self.desired_steer = turning_angle.abs().min(FRAC_PI_4) / FRAC_PI_4 * MAX_STEER * turning_angle.signum() + taget_steering * orientation_bias ;
+/// Another car on the road, as seen from this one
+struct Leader {
+    /// along the route, from center to center
+    distance: f32,
+    /// between the bumpers
+    gap: f32,
+    /// along the route
+    speed: f32,
+}
 
-        self.debug.desired_position = Some(desired_position);
-        self.debug.angle_to_position = Some(angle_to_position);
-        self.debug.angle_to_orientation = Some(angle_to_orientation);
-        self.debug.turning_angle = Some(turning_angle);
+fn find_leader(car: &Observation) -> Option<Leader> {
+    car.nearby_cars.iter().filter_map(|other| {
+        let max_lateral = (car.params.width + other.width) / 2.0;
+        let (distance, road_orientation) = car.navigator.find_ahead(other.position.coordinates, SPEED_PLANNING_DISTANCE, max_lateral)?;
+        Some(Leader {
+            distance,
+            gap: distance - (car.params.length + other.length) / 2.0,
+            speed: other.speed * (other.position.orientation - road_orientation).cos(),
+        })
+    }).min_by(|a, b| a.distance.total_cmp(&b.distance))
+}
 
-        if on_road_end {
-            self.navigator.switch_to_next_road();
+/// Closest place on the route car has to stop at: end of a road with a red light, or with an amber one, or before
+/// an intersection it is too early for, or the edge of a merge with a stop sign or where somebody else goes first. Cars that are too close to stop,
+/// except for the red light, go on.
+fn find_stop_line(car: &Observation) -> Option<Leader> {
+    let navigator = car.navigator;
+    let map = navigator.get_map();
+    let mut road = navigator.get_road_id();
+    let mut distance = navigator.get_road().length() - navigator.get_progress();
+    let mut upcoming = navigator.get_upcoming_road_ids().peekable();
+    while distance <= SPEED_PLANNING_DISTANCE {
+        let gap_to = |line: f32| line - car.params.length / 2.0;
+        let able_to_stop = |line: f32| car.speed.powi(2) / (2.0 * car.params.brakes) <= gap_to(line);
+        let light = car.signals.iter().find(|signal| signal.road == road).map(|signal| signal.state);
+        // car that could only crawl up to the intersection before its slot waits there instead
+        let early = car.slot.is_some_and(|slot| map.get_next_roads(road).contains(&slot.road) && gap_to(distance) < slot.arrival * CREEP_SPEED);
+        if light == Some(LightState::Red) || ((light == Some(LightState::Amber) || early) && able_to_stop(distance)) {
+            return Some(Leader { distance, gap: gap_to(distance), speed: 0.0 });
+        }
+        // only the merge the route goes through matters, not the ones of the other branches
+        let zone = upcoming.peek().and_then(|next_road| map.get_conflict_zone(road, *next_road));
+        // cars waiting for their turn keep clear of the other roads of the merge
+        let waiting_line = distance - zone.map_or(0.0, |zone| zone.get_waiting_distance(road));
+        // car that has come up to the stop sign has stopped there, now it only gives way
+        let stop_sign = map.get_lane(road).priority == Priority::Stop && gap_to(waiting_line) > MIN_GAP + STOP_SIGN_TOLERANCE;
+        let gives_way = distance <= GIVE_WAY_DISTANCE && zone.is_some_and(|zone| must_give_way(car, zone, road, distance));
+        if (stop_sign || gives_way) && able_to_stop(waiting_line) {
+            return Some(Leader { distance: waiting_line, gap: gap_to(waiting_line), speed: 0.0 });
         }
+        road = upcoming.next()?;
+        distance += map.get_road_by_id(road).length();
     }
+    None
 }
 
-struct DesiredPosition {
-    position: Position,
-    on_road_end: bool
-}
-
-fn get_desired_position_on_a_road(position: &Position, road: &Road) -> DesiredPosition {
-    match road {
-        crate::model::Road::Turn { coordinates, radius, start_angle, end_angle, direction } => {
-            let rel_x = position.coordinates.0 - coordinates.0;
-            let rel_y = position.coordinates.1 - coordinates.1;
-            let angle = rel_y.atan2(rel_x);
-            let rel_mag = ( rel_x * rel_x + rel_y * rel_y ).sqrt();
-            let distance = rel_mag - radius;
-            DesiredPosition { position: Position {
-                coordinates: (
-                    position.coordinates.0 - distance * angle.cos(),
-                    position.coordinates.1 - distance * angle.sin()
-                ),
-                orientation: angle + match direction {
-                    RoadTurnDirection::CW => -FRAC_PI_2,
-                    RoadTurnDirection::CCW => FRAC_PI_2,
-                }
-            }, on_road_end: match direction {
-                RoadTurnDirection::CW =>  *end_angle   > angle && angle > *start_angle,
-                RoadTurnDirection::CCW => *start_angle > angle && angle > *end_angle,
-            }}
-        },
-        crate::model::Road::Line { start, end } => {
-            let start_to_end = (end.0 - start.0 , end.1 - start.1);
-            let start_to_me = (position.coordinates.0 - start.0 , position.coordinates.1 - start.1);
-            let start_to_end_sq_mag = start_to_end.0.powi(2) + start_to_end.1.powi(2);
-            let dot_product = start_to_me.0 * start_to_end.0 + start_to_me.1 * start_to_end.1;
-            let normalized_distance = dot_product / start_to_end_sq_mag;
-            DesiredPosition { position: Position {
-                coordinates: (
-                    start.0 + start_to_end.0 * normalized_distance,
-                    start.1 + start_to_end.1 * normalized_distance
-                ),
-                orientation: start_to_end.1.atan2(start_to_end.0)
-            }, on_road_end: normalized_distance > 1.0 }
-        },
-    }
-}
-
-fn get_taget_steering(car: &Car, road: &Road) -> f32 {
-    let original_target_steering = get_target_steering_for_road(road);
-    let future_target_steering = get_target_steering_for_road(car.navigator.get_next_road());
-
-    let mean_between_original_and_future = (future_target_steering + original_target_steering) / 2.0;
-    let diff_to_future = mean_between_original_and_future - original_target_steering;
-    let distance_to_reach_half_of_future_steering = (car.speed * diff_to_future / STEER_SPEED).abs();
-
-    let end = road.get_end();
-    let distance_to_road_end = ((car.position.coordinates.0 - end.0).powi(2) + (car.position.coordinates.1 - end.1).powi(2)).sqrt();
-
-    if distance_to_road_end > distance_to_reach_half_of_future_steering {
-        original_target_steering
-    } else {
-        mean_between_original_and_future
-    }
-}
-
-fn get_target_steering_for_road(road: &Road) -> f32 {
-    match road {
-        crate::model::Road::Turn { radius, direction, .. } => match direction {
-            RoadTurnDirection::CW => -1.0 / radius,
-            RoadTurnDirection::CCW => 1.0 / radius,
+/// Whether a car coming from another road to the merge `zone` at the end of `road`, `distance` ahead, goes first.
+/// Cars on roads with a higher priority go first unless they are far enough, among equals the one that arrives first goes,
+/// and if they arrive at once, the one on the road with the lower index.
+fn must_give_way(car: &Observation, zone: &ConflictZone, road: RoadIndex, distance: f32) -> bool {
+    let map = car.navigator.get_map();
+    let priority = map.get_lane(road).priority;
+    let arrival = |distance: f32, length: f32, speed: f32| (distance - length / 2.0) / speed.max(CREEP_SPEED);
+    let own_arrival = arrival(distance, car.params.length, car.speed);
+
+    zone.incoming.iter().filter(|other_road| **other_road != road).any(|&other_road| {
+        let other_priority = map.get_lane(other_road).priority;
+        if other_priority < priority { return false; }
+        find_cars_coming(car, other_road).into_iter().any(|(to_merge, other)| {
+            let other_arrival = arrival(to_merge, other.length, other.speed);
+            if other_priority > priority {
+                to_merge - (car.params.length + other.length) / 2.0 <= other.speed * GIVE_WAY_HEADWAY
+            } else {
+                other_arrival.total_cmp(&own_arrival).then(other_road.cmp(&road)).is_lt()
+            }
+        })
+    })
+}
+
+/// Nearby cars on `road` and on the roads leading into it up to `GIVE_WAY_DISTANCE` back,
+/// with how far each of them still has to go to the end of `road`
+fn find_cars_coming<'a>(car: &Observation<'a>, road: RoadIndex) -> Vec<(f32, &'a NearbyCar)> {
+    let map = car.navigator.get_map();
+    let mut found = Vec::new();
+    let mut visited = vec![road];
+    let mut roads = vec![(road, 0.0)];
+    while let Some((road, after)) = roads.pop() {
+        let (lane, length) = (map.get_lane(road), map.get_road_by_id(road).length());
+        for other in car.nearby_cars {
+            let (s, lateral) = map.get_road_by_id(road).project(other.position.coordinates);
+            if (0.0..=length).contains(&s) && lateral.abs() <= lane.width / 2.0 { found.push((after + length - s, other)); }
+        }
+        if after + length >= GIVE_WAY_DISTANCE { continue; }
+        for previous in map.get_previous_roads(road) {
+            if !visited.contains(previous) {
+                visited.push(*previous);
+                roads.push((*previous, after + length));
+            }
         }
-        ,
-        crate::model::Road::Line { .. } => 0.0,
     }
+    found
+}
+
+/// Highest speed at which the front of the car does not get to the road of its slot before its time
+pub(crate) fn get_speed_limit_for_slot(car: &Observation) -> f32 {
+    let Some(slot) = car.slot.filter(|slot| slot.arrival > 0.0) else { return f32::INFINITY };
+    let navigator = car.navigator;
+    let map = navigator.get_map();
+    let mut distance = navigator.get_road().length() - navigator.get_progress();
+    for road in navigator.get_upcoming_road_ids() {
+        if road == slot.road { return (distance - car.params.length / 2.0).max(0.0) / slot.arrival; }
+        if distance > SPEED_PLANNING_DISTANCE { break; }
+        distance += map.get_road_by_id(road).length();
+    }
+    f32::INFINITY
+}
+
+/// Highest speed at which car can still stop before the stop line it has to stop at
+pub(crate) fn get_speed_limit_for_stop_line(car: &Observation) -> f32 {
+    find_stop_line(car)
+        .map(|stop_line| (2.0 * car.params.brakes * (stop_line.gap - MIN_GAP).max(0.0)).sqrt())
+        .unwrap_or(f32::INFINITY)
+}
+
+/// Closest cars in front and behind on `lane`, which runs alongside the current one
+fn find_cars_on_lane(car: &Observation, lane: RoadIndex) -> (Option<Leader>, Option<Leader>) {
+    let road = car.navigator.get_map().get_road_by_id(lane);
+    let (progress, _) = road.project(car.position.coordinates);
+    let (mut ahead, mut behind): (Option<Leader>, Option<Leader>) = (None, None);
+    for other in car.nearby_cars {
+        let (s, lateral) = road.project(other.position.coordinates);
+        if lateral.abs() > (car.params.width + other.width) / 2.0 { continue; }
+        let distance = (s - progress).abs();
+        let found = Leader {
+            distance,
+            gap: distance - (car.params.length + other.length) / 2.0,
+            speed: other.speed * (other.position.orientation - road.tangent_at(s)).cos(),
+        };
+        let closest = if s >= progress { &mut ahead } else { &mut behind };
+        if closest.as_ref().is_none_or(|closest| found.distance < closest.distance) { *closest = Some(found); }
+    }
+    (ahead, behind)
 }
 
-fn get_orientation_bias(distance: f32) -> f32 {
-    let turing_radius = 1.0 / MAX_STEER;
+/// Lane to change into right now, if any. Route may ask for a lane change, otherwise car without
+/// a destination overtakes slow leaders on the left and keeps to the right once they are behind.
+/// Lane change only starts when there is a gap for the car on the new lane.
+pub(crate) fn plan_lane_change(car: &Observation) -> Option<RoadIndex> {
+    if car.gear != Gear::Drive { return None; }
+    let lane = car.navigator.get_map().get_lane(car.navigator.get_road_id());
+    // previous lane change is not over yet
+    let (_, lateral) = car.navigator.get_road().project(car.position.coordinates);
+    if lateral.abs() > lane.width * LANE_CHANGE_SETTLED { return None; }
+
+    let is_slow = |leader: Option<Leader>| leader
+        .is_some_and(|leader| leader.gap < OVERTAKING_DISTANCE && leader.speed < car.cruise_speed - OVERTAKING_SPEED_GAIN);
+    let target = match car.navigator.get_lane_change() {
+        Some(target) => target,
+        // cars going somewhere keep to the lanes of their route
+        None if car.navigator.get_destination().is_some() => return None,
+        None if is_slow(find_leader(car)) => lane.left.filter(|left| !is_slow(find_cars_on_lane(car, *left).0))?,
+        None => lane.right.filter(|right| !is_slow(find_cars_on_lane(car, *right).0))?,
+    };
+
+    let (ahead, behind) = find_cars_on_lane(car, target);
+    let room_ahead = ahead.is_none_or(|ahead| ahead.gap >= MIN_GAP + car.speed * LANE_CHANGE_HEADWAY);
+    let room_behind = behind.is_none_or(|behind| behind.gap >= MIN_GAP + behind.speed * LANE_CHANGE_HEADWAY);
+    (room_ahead && room_behind).then_some(target)
+}
+
+/// Intelligent Driver Model: acceleration that keeps `TIME_HEADWAY` to the leader
+/// and approaches it smoothly, while going as fast as `free_speed` on a free road
+fn get_following_acceleration(car: &Observation, free_speed: f32, leader: &Leader, speed: f32) -> f32 {
+    let max_acceleration = car.params.acceleration;
+    let comfortable_deceleration = car.params.brakes / 2.0;
+    let approach_rate = speed - leader.speed;
+    let desired_gap = (MIN_GAP + speed * TIME_HEADWAY
+        + speed * approach_rate / (2.0 * (max_acceleration * comfortable_deceleration).sqrt())).max(MIN_GAP);
+    let gap = leader.gap.max(f32::EPSILON);
+    max_acceleration * (1.0 - (speed / free_speed).powi(FREE_ROAD_EXPONENT) - (desired_gap / gap).powi(2))
+}
+
+/// Speed at which car following would neither accelerate nor slow down.
+/// Acceleration only drops with the speed, so it is found by bisection.
+fn get_following_speed(car: &Observation, free_speed: f32, leader: &Leader) -> f32 {
+    if free_speed <= 0.0 || get_following_acceleration(car, free_speed, leader, 0.0) <= 0.0 {
+        return 0.0;
+    }
+    let (mut low, mut high) = (0.0, free_speed);
+    for _ in 0..20 {
+        let middle = (low + high) / 2.0;
+        if get_following_acceleration(car, free_speed, leader, middle) > 0.0 { low = middle; } else { high = middle; }
+    }
+    low
+}
+
+/// Curvature of the road up to `lookahead`, for debugging. Last pair only marks where it ends.
+pub(crate) fn get_curvature_profile(car: &Observation, lookahead: f32) -> Vec<(f32, f32)> {
+    let mut profile: Vec<(f32, f32)> = car.navigator.get_curvature_ahead(lookahead).collect();
+    let (_, last_curvature) = *profile.last().expect("Current road is always there");
+    profile.push((lookahead, last_curvature));
+    profile
+}
+
+/// Curvature to hold on the road, which moves halfway to the curvature of an upcoming
+/// road once it's time to start turning into it. Roads further than `lookahead` are not looked at.
+pub(crate) fn get_taget_steering(car: &Observation, lookahead: f32) -> f32 {
+    let mut curvature_ahead = car.navigator.get_curvature_ahead(lookahead);
+    let (_, original_target_steering) = curvature_ahead.next().expect("Current road is always there");
+
+    for (distance_to_road, future_target_steering) in curvature_ahead {
+        let mean_between_original_and_future = (future_target_steering + original_target_steering) / 2.0;
+        let diff_to_future = mean_between_original_and_future - original_target_steering;
+        let distance_to_reach_half_of_future_steering = (car.speed * diff_to_future / car.vehicle_model.get_steer_speed(car.params)).abs();
+
+        if distance_to_road <= distance_to_reach_half_of_future_steering {
+            return mean_between_original_and_future;
+        }
+    }
+    original_target_steering
+}
+
+/// Highest speed at which car is still able to turn the steering wheel from
+/// one curvature to another within `MAX_STEERING_TRANSITION`.
+fn get_speed_limit_for_steering_change(car: &Observation, steering_change: f32) -> f32 {
+    car.vehicle_model.get_steer_speed(car.params) * MAX_STEERING_TRANSITION / steering_change.abs()
+}
+
+/// Highest speed at which tires still hold the car in a turn of `curvature`
+fn get_speed_limit_for_curvature(car: &Observation, curvature: f32) -> f32 {
+    match car.vehicle_model.get_max_lateral_acceleration() {
+        Some(max_lateral_acceleration) => (max_lateral_acceleration * LATERAL_GRIP_MARGIN / curvature.abs()).sqrt(),
+        None => f32::INFINITY,
+    }
+}
+
+/// Looks at the upcoming roads and picks a speed that lets the car brake down
+/// in time for every change of curvature ahead.
+pub(crate) fn get_planned_speed(car: &Observation, road: &Road, progress: f32) -> f32 {
+    let deceleration = car.params.brakes;
+
+    let mut distance_to_next_road = road.length() - progress;
+    let mut previous_steering = road.curvature_at(progress);
+    let mut planned_speed = car.cruise_speed.min(get_speed_limit_for_curvature(car, previous_steering));
+
+    for next_road in car.navigator.get_upcoming_roads() {
+        if distance_to_next_road > SPEED_PLANNING_DISTANCE { break; }
+
+        let steering = next_road.curvature_at(0.0);
+        let speed_limit = get_speed_limit_for_steering_change(car, steering - previous_steering)
+            .min(get_speed_limit_for_curvature(car, steering));
+        // steering transition starts before the road does
+        let braking_distance = (distance_to_next_road - MAX_STEERING_TRANSITION / 2.0).max(0.0);
+        planned_speed = planned_speed.min((speed_limit.powi(2) + 2.0 * deceleration * braking_distance).sqrt());
+
+        distance_to_next_road += next_road.length();
+        previous_steering = steering;
+    }
+
+    planned_speed
+}
+
+/// Highest speed at which car can still stop behind every car that is in its way,
+/// even if that car starts braking right now.
+pub(crate) fn get_speed_limit_for_nearby_cars(car: &Observation, gear: Gear) -> f32 {
+    let deceleration = car.params.brakes;
+    // backing up car looks behind
+    let heading = match gear {
+        Gear::Reverse => car.position.orientation + PI,
+        _ => car.position.orientation,
+    };
+    let (sin, cos) = heading.sin_cos();
+
+    car.nearby_cars.iter().filter_map(|other| {
+        let dx = other.position.coordinates.0 - car.position.coordinates.0;
+        let dy = other.position.coordinates.1 - car.position.coordinates.1;
+        let ahead = dx * cos + dy * sin;
+        let aside = -dx * sin + dy * cos;
+        // cars which are closer than that to the line car is heading along are in the way
+        let path_half_width = (car.params.width + other.width) / 2.0;
+        if ahead <= 0.0 || aside.abs() > path_half_width { return None; }
+
+        let gap = (ahead - (car.params.length + other.length) / 2.0 - MIN_GAP).max(0.0);
+        // only the part of other car's speed that takes it away from us
+        let other_speed = (other.speed * (other.position.orientation - heading).cos()).max(0.0);
+        Some((other_speed.powi(2) + 2.0 * deceleration * gap).sqrt())
+    }).fold(f32::INFINITY, f32::min)
+}
+
+fn get_orientation_bias(distance: f32, max_steer: f32) -> f32 {
+    let turing_radius = 1.0 / max_steer;
     (turing_radius*2.0 - distance).max(0.0) / (turing_radius*2.0)
 }
+
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+    use crate::physics::Physics;
+    use crate::model::{RoadTurnDirection, TireModel, DEFAULT_CRUISE_SPEED as CRUISE_SPEED, GRAVITY};
+    use crate::navigator::{road_nodes_from_toml, Navigator, RoadMap, RoadNode};
+    use crate::reservations::Slot;
+    use crate::signals::{LightState, Signal};
+
+    fn car_before_sharp_turn(distance_to_turn: f32) -> Car {
+        let road_map = RoadMap::new(vec![
+            RoadNode { road: Road::Line { start: (0., 0.), end: (1000., 0.) }, next: vec![1] },
+            RoadNode { road: Road::Turn { coordinates: (1000., 60.), radius: 60., start_angle: -FRAC_PI_2, end_angle: FRAC_PI_2, direction: RoadTurnDirection::CCW }, next: vec![2] },
+            RoadNode { road: Road::Line { start: (1000., 120.), end: (0., 120.) }, next: vec![0] },
+        ]).expect("Should have created RoadMap");
+        let navigator = Navigator::new(Rc::new(road_map), 0).expect("Should have created the navigator");
+        let mut car = Car::from_navigator(navigator);
+        car.position.coordinates = (1000. - distance_to_turn, 0.);
+        car.navigator.set_progress(1000. - distance_to_turn);
+        car.speed = CRUISE_SPEED;
+        car
+    }
+
+    #[test]
+    fn keeps_cruise_speed_far_from_turn() {
+        let car = car_before_sharp_turn(900.);
+        assert_eq!(get_planned_speed(&car.observe(&[], &[]), car.navigator.get_road(), 100.), CRUISE_SPEED);
+    }
+
+    #[test]
+    fn brakes_before_sharp_turn() {
+        let mut car = car_before_sharp_turn(50.);
+        car.think(&[], &[]);
+        assert!(car.desired_speed < CRUISE_SPEED);
+        assert!(car.brakes);
+    }
+
+    #[test]
+    fn plans_for_lateral_grip_of_dynamic_model() {
+        let mut car = car_before_sharp_turn(900.);
+        car.vehicle_model = VehicleModel::Dynamic { wheelbase: 24., steering_ratio: 15., friction: 0.9, front_stiffness: 8., rear_stiffness: 8., tire: TireModel::Linear };
+        let grip_limit = (0.9 * GRAVITY * LATERAL_GRIP_MARGIN * 60.).sqrt();
+
+        car.navigator.set_progress(1000.);
+        assert!(get_planned_speed(&car.observe(&[], &[]), car.navigator.get_road(), 1000.) <= grip_limit);
+        // far from the turn, there is enough room to brake
+        assert_eq!(get_planned_speed(&car.observe(&[], &[]), car.navigator.get_road(), 100.), CRUISE_SPEED);
+    }
+
+    #[test]
+    fn plans_for_steering_wheel_of_bicycle_model() {
+        let mut car = car_before_sharp_turn(900.);
+        car.navigator.set_progress(1000.);
+        let planned_speed = |car: &Car| get_planned_speed(&car.observe(&[], &[]), car.navigator.get_road(), 1000.);
+        let point = planned_speed(&car);
+
+        // slow steering wheel turns the front wheels slower than the point model steers
+        car.vehicle_model = VehicleModel::Bicycle { wheelbase: 24., steering_ratio: 15. };
+        car.params.steering_wheel_speed = 3.;
+        let bicycle = planned_speed(&car);
+        let expected = point * (3. / 15. / 24.) / car.params.steer_speed;
+        assert!((bicycle - expected).abs() < 1e-3, "{bicycle} {expected}");
+    }
+
+    #[test]
+    fn brakes_behind_stopped_car() {
+        let mut car = car_before_sharp_turn(900.);
+        let stopped = NearbyCar { position: Position { coordinates: (160., 0.), orientation: 0. }, speed: 0., steer: 0., length: 40., width: 20. };
+        car.think(std::slice::from_ref(&stopped), &[]);
+        assert!(car.brakes);
+
+        // same car on the neighbouring lane is not in the way
+        let mut car = car_before_sharp_turn(900.);
+        let aside = NearbyCar { position: Position { coordinates: (160., 40.), ..stopped.position }, ..stopped };
+        car.think(&[aside], &[]);
+        assert!(!car.brakes);
+    }
+
+    #[test]
+    fn stops_at_red_light() {
+        let light = |state| [Signal { road: 0, state }];
+        let mut car = car_before_sharp_turn(300.);
+        car.think(&[], &light(LightState::Green));
+        let free_speed = car.desired_speed;
+        assert_eq!(car.debug.gap, None);
+
+        car.think(&[], &light(LightState::Red));
+        assert!(car.desired_speed < free_speed);
+        assert_eq!(car.debug.gap, Some(300. - car.params.length / 2.));
+        // amber is as good as red for a car that is able to stop
+        car.think(&[], &light(LightState::Amber));
+        assert_eq!(car.debug.gap, Some(300. - car.params.length / 2.));
+        // but the one that is too close goes on
+        let mut car = car_before_sharp_turn(40.);
+        car.think(&[], &light(LightState::Amber));
+        assert_eq!(car.debug.gap, None);
+    }
+
+    #[test]
+    fn adjusts_speed_to_arrive_in_its_slot() {
+        let mut car = car_before_sharp_turn(300.);
+        car.think(&[], &[]);
+        let free_speed = car.desired_speed;
+
+        // slot is ten seconds away, so there is no hurry
+        car.slot = Some(Slot { road: 1, arrival: 10. });
+        car.think(&[], &[]);
+        assert!(car.debug.planned_speed.is_some_and(|speed| speed <= (300. - car.params.length / 2.) / 10.), "{:?}", car.debug.planned_speed);
+        assert_eq!(car.debug.gap, None);
+        // crawling there would take too long, car rather waits at the intersection
+        car.slot = Some(Slot { road: 1, arrival: 60. });
+        car.think(&[], &[]);
+        assert_eq!(car.debug.gap, Some(300. - car.params.length / 2.));
+        // slot that has already started does not hold the car back
+        car.slot = Some(Slot { road: 1, arrival: 0. });
+        car.think(&[], &[]);
+        assert_eq!(car.desired_speed, free_speed);
+    }
+
+    #[test]
+    fn stops_at_stop_sign_even_if_nobody_comes() {
+        let map = Rc::new(road_nodes_from_toml(r#"
+            [roads]
+            a = { type = 'Line', start_x = 0.0, start_y = 0.0, end_x = 1000.0, end_y = 0.0, priority = 'Stop' }
+            b = { type = 'Line', start_x = 1000.0, start_y = 0.0, end_x = 0.0, end_y = 0.0 }
+            [nodes]
+            a = ['b']
+            b = ['a']
+        "#).expect("Should have read the map"));
+        let car_at = |distance_to_line: f32, speed: f32| {
+            let mut navigator = Navigator::new(Rc::clone(&map), 0).expect("Should have created the navigator");
+            navigator.set_progress(1000. - distance_to_line);
+            let mut car = Car::from_navigator(navigator);
+            car.position.coordinates = (1000. - distance_to_line, 0.);
+            car.speed = speed;
+            car
+        };
+
+        let mut car = car_at(300., CRUISE_SPEED);
+        car.think(&[], &[]);
+        assert_eq!(car.debug.gap, Some(300. - car.params.length / 2.));
+        // once it has stopped at the line, it may go
+        let mut car = car_at(car.params.length / 2. + MIN_GAP, 0.);
+        car.think(&[], &[]);
+        assert_eq!(car.debug.gap, None);
+    }
+
+    #[test]
+    fn gives_way_only_at_merge_on_its_route() {
+        // `a` branches into `b` and `c`, and `d` merges into `b` right there
+        let map = Rc::new(road_nodes_from_toml(r#"
+            [roads]
+            a = { type = 'Line', start_x = 0.0, start_y = 0.0, end_x = 500.0, end_y = 0.0, priority = 'Minor' }
+            b = { type = 'Line', start_x = 500.0, start_y = 0.0, end_x = 1000.0, end_y = 0.0 }
+            c = { type = 'Line', start_x = 500.0, start_y = 0.0, end_x = 500.0, end_y = 500.0 }
+            d = { type = 'Line', start_x = 500.0, start_y = -500.0, end_x = 500.0, end_y = 0.0 }
+            [nodes]
+            a = ['b', 'c']
+            b = ['a']
+            c = ['a']
+            d = ['b']
+        "#).expect("Should have read the map"));
+        let road = |key: &str| map.get_road_index_by_key(key).expect("Road should exist");
+        let crossing = NearbyCar { position: Position { coordinates: (500., -150.), orientation: FRAC_PI_2 }, speed: 100., steer: 0., length: 40., width: 20. };
+        let gap_to_merge = |destination: &str| {
+            let mut navigator = Navigator::new(Rc::clone(&map), road("a")).expect("Should have created the navigator");
+            navigator.set_destination(road(destination)).expect("Destination should be reachable");
+            navigator.set_progress(200.);
+            let mut car = Car::from_navigator(navigator);
+            car.position.coordinates = (200., 0.);
+            car.speed = CRUISE_SPEED;
+            car.think(std::slice::from_ref(&crossing), &[]);
+            car.debug.gap
+        };
+
+        assert!(gap_to_merge("b").is_some());
+        // turning off before the merge, nobody to give way to
+        assert_eq!(gap_to_merge("c"), None);
+    }
+
+    #[test]
+    fn gives_way_on_roundabout() {
+        let map = Rc::new(road_nodes_from_toml(include_str!("../resources/roundabout.toml")).expect("Sample map should be valid"));
+        let mut navigator = Navigator::new(Rc::clone(&map), map.get_road_index_by_key("n_in").unwrap()).expect("Should have created the navigator");
+        navigator.set_progress(150.);
+        let mut car = Car::from_navigator(navigator);
+        car.position = Position { coordinates: (-40., 250.), orientation: -FRAC_PI_2 };
+        let [roundabout] = map.get_roundabouts() else { panic!("There should be one roundabout") };
+        let joint = roundabout.get_angle_of(map.get_road_by_id(map.get_next_roads(car.navigator.get_road_id())[0])).expect("Entry is a turn");
+        let circulating = |angle: f32| NearbyCar {
+            position: Position { coordinates: (roundabout.radius * angle.cos(), roundabout.radius * angle.sin()), orientation: angle + FRAC_PI_2 },
+            speed: 100., steer: 1. / roundabout.radius, length: 40., width: 20.,
+        };
+
+        car.think(&[], &[]);
+        assert_eq!(car.debug.gap, None);
+        car.think(&[circulating(joint - 0.8)], &[]);
+        assert!(car.debug.gap.is_some());
+        // car that has already passed, and is not ahead on the way out either, is no reason to wait
+        car.think(&[circulating(joint + 2.5)], &[]);
+        assert_eq!(car.debug.gap, None);
+    }
+
+    #[test]
+    fn waits_for_gap_to_overtake() {
+        let map = road_nodes_from_toml(r#"
+            [roads]
+            a = { type = 'Line', start_x = 0.0, start_y = 0.0, end_x = 1000.0, end_y = 0.0, lanes = [{}, {}] }
+            b = { type = 'Line', start_x = 1000.0, start_y = 0.0, end_x = 0.0, end_y = 0.0, lanes = [{}, {}] }
+            [nodes]
+            a = ['b']
+            b = ['a']
+        "#).expect("Should have read the map");
+        let (left, right) = (map.get_lane_index_by_key("a", 0).unwrap(), map.get_lane_index_by_key("a", 1).unwrap());
+        let mut car = Car::from_navigator(Navigator::new(Rc::new(map), right).expect("Should have created the navigator"));
+        car.position.coordinates = (100., -20.);
+        car.navigator.set_progress(100.);
+        car.speed = CRUISE_SPEED;
+        let car_at = |x: f32, y: f32, speed: f32| NearbyCar { position: Position { coordinates: (x, y), orientation: 0. }, speed, steer: 0., length: 40., width: 20. };
+        let slow = car_at(200., -20., 50.);
+
+        assert_eq!(plan_lane_change(&car.observe(std::slice::from_ref(&slow), &[])), Some(left));
+        // somebody is driving on the left lane right next to the car
+        assert_eq!(plan_lane_change(&car.observe(&[slow, car_at(80., 20., CRUISE_SPEED)], &[])), None);
+        // with nobody to overtake, car keeps right
+        assert_eq!(plan_lane_change(&car.observe(&[], &[])), None);
+    }
+
+    #[test]
+    fn backs_up_to_turn_around() {
+        let mut car = car_before_sharp_turn(900.);
+        car.position.orientation = PI;
+        car.speed = 0.;
+
+        car.think(&[], &[]);
+        assert_eq!(car.desired_gear, Gear::Reverse);
+
+        let mut reversed = false;
+        for _ in 0..600 {
+            car.think(&[], &[]);
+            car.update(1.0 / 60.0);
+            reversed |= car.speed < 0.;
+        }
+        assert!(reversed);
+        assert_eq!(car.gear, Gear::Drive);
+        assert!(normalize_angle(car.position.orientation).abs() < FRAC_PI_4);
+    }
+
+    #[test]
+    fn sees_turn_behind_short_road() {
+        let road_map = RoadMap::new(vec![
+            RoadNode { road: Road::Line { start: (0., 0.), end: (1000., 0.) }, next: vec![1] },
+            RoadNode { road: Road::Line { start: (1000., 0.), end: (1010., 0.) }, next: vec![2] },
+            RoadNode { road: Road::Turn { coordinates: (1010., 60.), radius: 60., start_angle: -FRAC_PI_2, end_angle: FRAC_PI_2, direction: RoadTurnDirection::CCW }, next: vec![0] },
+        ]).expect("Should have created RoadMap");
+        let mut car = Car::from_navigator(Navigator::new(Rc::new(road_map), 0).expect("Should have created the navigator"));
+        car.navigator.set_progress(990.);
+        car.speed = CRUISE_SPEED;
+
+        assert_eq!(get_taget_steering(&car.observe(&[], &[]), DEFAULT_LOOKAHEAD), 1. / 60. / 2.);
+        // the turn is further than the car looks
+        assert_eq!(get_taget_steering(&car.observe(&[], &[]), 15.), 0.);
+    }
+}
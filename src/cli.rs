@@ -0,0 +1,131 @@
+use std::path::PathBuf;
+
+use driving_algorithm::physics::DEFAULT_UPS;
+
+pub const USAGE: &str = "\
+Usage: driving-algorithm [OPTIONS] [MAP]
+
+Arguments:
+  [MAP]                 map file with roads and cars [default: resources/map.toml]
+
+Options:
+  --debug               draw what cars are thinking
+  --headless            run without a window, as fast as possible (needs --ticks)
+  --ticks <N>           stop after N ticks of simulation
+  --time-scale <X>      run simulation X times faster than real time, not with --headless [default: 1]
+  --ups <N>             physics updates per second of simulated time [default: 60]
+  --output <DIR>        write telemetry of every tick into DIR
+  --verbose             print every event
+  --help                print this message";
+
+#[derive(Debug)]
+pub struct Arguments {
+    pub map_path: PathBuf,
+    pub debug: bool,
+    pub headless: bool,
+    pub ticks: Option<u64>,
+    pub time_scale: f32,
+    pub ups: f32,
+    pub output: Option<PathBuf>,
+    pub verbose: bool,
+}
+
+#[derive(Debug)]
+pub enum ArgumentsError {
+    HelpRequested,
+    UnknownArgument { argument: String },
+    MissingValue { argument: String },
+    InvalidValue { argument: String, value: String },
+    HeadlessWithoutTicks,
+    /// headless simulation runs as fast as it can, there is no real time to scale
+    HeadlessWithTimeScale,
+}
+
+impl Arguments {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, ArgumentsError> {
+        let mut arguments = Arguments {
+            map_path: PathBuf::from("resources/map.toml"),
+            debug: false,
+            headless: false,
+            ticks: None,
+            time_scale: 1.0,
+            ups: DEFAULT_UPS,
+            output: None,
+            verbose: false,
+        };
+        let mut map_path = None;
+        let mut time_scale = None;
+
+        while let Some(argument) = args.next() {
+            let mut value_of = |argument: &str| args.next().ok_or_else(|| ArgumentsError::MissingValue { argument: argument.to_string() });
+            match argument.as_str() {
+                "--help" | "-h" => return Err(ArgumentsError::HelpRequested),
+                "--debug" => arguments.debug = true,
+                "--headless" => arguments.headless = true,
+                "--ticks" => {
+                    let value = value_of(&argument)?;
+                    arguments.ticks = Some(value.parse().map_err(|_| ArgumentsError::InvalidValue { argument, value })?);
+                },
+                "--time-scale" => {
+                    let value = value_of(&argument)?;
+                    time_scale = Some(value.parse().ok().filter(|v: &f32| *v > 0.0)
+                        .ok_or(ArgumentsError::InvalidValue { argument, value })?);
+                },
+                "--ups" => {
+                    let value = value_of(&argument)?;
+                    arguments.ups = value.parse().ok().filter(|v: &f32| *v > 0.0)
+                        .ok_or(ArgumentsError::InvalidValue { argument, value })?;
+                },
+                "--output" => arguments.output = Some(PathBuf::from(value_of(&argument)?)),
+                "--verbose" => arguments.verbose = true,
+                _ if argument.starts_with('-') || map_path.is_some() => return Err(ArgumentsError::UnknownArgument { argument }),
+                _ => map_path = Some(PathBuf::from(argument)),
+            }
+        }
+
+        if let Some(map_path) = map_path { arguments.map_path = map_path; }
+        if arguments.headless && arguments.ticks.is_none() { return Err(ArgumentsError::HeadlessWithoutTicks); }
+        if arguments.headless && time_scale.is_some() { return Err(ArgumentsError::HeadlessWithTimeScale); }
+        if let Some(time_scale) = time_scale { arguments.time_scale = time_scale; }
+        Ok(arguments)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(command_line: &str) -> Result<Arguments, ArgumentsError> {
+        Arguments::parse(command_line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn reads_full_command_line() {
+        let arguments = parse("--debug --ticks 600 --time-scale 2 --ups 120 --output telemetry --verbose maps/city.toml").expect("Arguments should be valid");
+
+        assert_eq!(arguments.map_path, PathBuf::from("maps/city.toml"));
+        assert!(arguments.debug && arguments.verbose && !arguments.headless);
+        assert_eq!(arguments.ticks, Some(600));
+        assert_eq!((arguments.time_scale, arguments.ups), (2.0, 120.0));
+        assert_eq!(arguments.output, Some(PathBuf::from("telemetry")));
+
+        let defaults = parse("").expect("No arguments should be valid");
+        assert_eq!(defaults.map_path, PathBuf::from("resources/map.toml"));
+        assert_eq!((defaults.ticks, defaults.time_scale, defaults.ups), (None, 1.0, DEFAULT_UPS));
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        assert!(matches!(parse("--help"), Err(ArgumentsError::HelpRequested)));
+        assert!(matches!(parse("--fast"), Err(ArgumentsError::UnknownArgument { argument }) if argument == "--fast"));
+        // second map is not expected
+        assert!(matches!(parse("a.toml b.toml"), Err(ArgumentsError::UnknownArgument { argument }) if argument == "b.toml"));
+        assert!(matches!(parse("--ticks"), Err(ArgumentsError::MissingValue { argument }) if argument == "--ticks"));
+        assert!(matches!(parse("--ups ten"), Err(ArgumentsError::InvalidValue { argument, value }) if argument == "--ups" && value == "ten"));
+        assert!(matches!(parse("--time-scale 0"), Err(ArgumentsError::InvalidValue { .. })));
+        assert!(matches!(parse("--headless"), Err(ArgumentsError::HeadlessWithoutTicks)));
+        assert!(matches!(parse("--headless --ticks 60 --time-scale 2"), Err(ArgumentsError::HeadlessWithTimeScale)));
+        assert!(parse("--headless --ticks 60").is_ok_and(|arguments| arguments.headless));
+    }
+}
@@ -0,0 +1,72 @@
+use crate::model::Position;
+
+/// Corners of the body, which is a rectangle of `(length, width)` centered on
+/// its coordinates and rotated by its orientation.
+pub fn get_body_corners(position: &Position, (length, width): (f32, f32)) -> [(f32, f32); 4] {
+    let (x, y) = position.coordinates;
+    let (sin, cos) = position.orientation.sin_cos();
+    let (hl, hw) = (length / 2.0, width / 2.0);
+    [(hl, hw), (-hl, hw), (-hl, -hw), (hl, -hw)]
+        .map(|(dx, dy)| (x + dx * cos - dy * sin, y + dx * sin + dy * cos))
+}
+
+/// Separating axis test for two oriented bodies, sizes are `(length, width)`.
+pub fn bodies_collide(a: &Position, a_size: (f32, f32), b: &Position, b_size: (f32, f32)) -> bool {
+    let corners_a = get_body_corners(a, a_size);
+    let corners_b = get_body_corners(b, b_size);
+    // two rectangles only have four distinct edge directions
+    let axes = [a.orientation, a.orientation + std::f32::consts::FRAC_PI_2,
+                b.orientation, b.orientation + std::f32::consts::FRAC_PI_2]
+        .map(|angle| (angle.cos(), angle.sin()));
+
+    axes.iter().all(|axis| {
+        let (min_a, max_a) = project_on_axis(&corners_a, *axis);
+        let (min_b, max_b) = project_on_axis(&corners_b, *axis);
+        min_a <= max_b && min_b <= max_a
+    })
+}
+
+fn project_on_axis(corners: &[(f32, f32); 4], axis: (f32, f32)) -> (f32, f32) {
+    corners.iter()
+        .map(|c| c.0 * axis.0 + c.1 * axis.1)
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), p| (min.min(p), max.max(p)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_4;
+
+    use super::*;
+    use crate::model::VehicleParams;
+
+    fn at(x: f32, y: f32, orientation: f32) -> Position {
+        Position { coordinates: (x, y), orientation }
+    }
+
+    fn cars_collide(a: Position, b: Position) -> bool {
+        let car = VehicleParams::car().get_size();
+        bodies_collide(&a, car, &b, car)
+    }
+
+    #[test]
+    fn detects_bumper_to_bumper_contact() {
+        assert!(cars_collide(at(0., 0., 0.), at(39., 0., 0.)));
+        assert!(!cars_collide(at(0., 0., 0.), at(41., 0., 0.)));
+    }
+
+    #[test]
+    fn uses_orientation_of_both_bodies() {
+        // side by side there is a gap, but rotated car swings its corner into the other one
+        assert!(!cars_collide(at(0., 0., 0.), at(0., 25., 0.)));
+        assert!(cars_collide(at(0., 0., 0.), at(0., 25., FRAC_PI_4)));
+        assert!(!cars_collide(at(0., 0., FRAC_PI_4), at(30., -30., FRAC_PI_4)));
+    }
+
+    #[test]
+    fn uses_size_of_each_vehicle() {
+        let (truck, car) = (VehicleParams::truck().get_size(), VehicleParams::car().get_size());
+        assert!(bodies_collide(&at(0., 0., 0.), truck, &at(60., 0., 0.), car));
+        assert!(!bodies_collide(&at(0., 0., 0.), car, &at(60., 0., 0.), car));
+    }
+}
@@ -0,0 +1,366 @@
+use crate::algorithm::{follow_planned_speed, get_curvature_profile, get_planned_speed, get_speed_limit_for_nearby_cars, get_speed_limit_for_slot, get_speed_limit_for_stop_line, get_taget_steering,
+                       Controller, Controls, Observation, DEFAULT_LOOKAHEAD};
+use crate::model::{Car, CarDebugInfo, Gear, Position};
+use crate::physics::Physics;
+use crate::util::normalize_angle;
+
+/// Steers along the arc that goes through a point on the road some distance ahead
+#[derive(Debug,Clone)]
+pub struct PurePursuit {
+    /// seconds of driving the goal point is ahead, on top of `min_lookahead`
+    pub lookahead_time: f32,
+    pub min_lookahead: f32,
+}
+
+impl Default for PurePursuit {
+    fn default() -> Self {
+        Self { lookahead_time: 0.3, min_lookahead: 40.0 }
+    }
+}
+
+impl Controller for PurePursuit {
+    fn control(&mut self, car: &Observation, debug: &mut CarDebugInfo) -> Controls {
+        let lookahead = self.min_lookahead + self.lookahead_time * car.speed.abs();
+        let goal = car.navigator.get_position_ahead(lookahead);
+
+        let dx = goal.coordinates.0 - car.position.coordinates.0;
+        let dy = goal.coordinates.1 - car.position.coordinates.1;
+        let distance = (dx * dx + dy * dy).sqrt().max(1.0);
+        let angle_to_goal = normalize_angle(dy.atan2(dx) - car.position.orientation);
+        // arc tangent to the car which passes through the goal
+        let curvature = 2.0 * angle_to_goal.sin() / distance;
+
+        debug.desired_position = Some(goal);
+        debug.angle_to_position = Some(angle_to_goal);
+
+        let max_steer = car.params.max_steer;
+        let road = car.navigator.get_road();
+        let planned_speed = get_planned_speed(car, road, car.navigator.get_progress());
+        follow_planned_speed(car, Gear::Drive, planned_speed, curvature.clamp(-max_steer, max_steer), debug)
+    }
+}
+
+/// Turns front wheels along the road, corrected by how far the front axle is off the road
+#[derive(Debug,Clone)]
+pub struct Stanley {
+    /// how hard the distance from the road is corrected, per second
+    pub gain: f32,
+    /// added to the speed, so that a slow car does not turn the wheels to the lock
+    pub softening: f32,
+}
+
+impl Default for Stanley {
+    fn default() -> Self {
+        Self { gain: 2.0, softening: 10.0 }
+    }
+}
+
+impl Controller for Stanley {
+    fn control(&mut self, car: &Observation, debug: &mut CarDebugInfo) -> Controls {
+        let wheelbase = car.vehicle_model.get_wheelbase(car.params);
+        let (sin, cos) = car.position.orientation.sin_cos();
+        let front_axle = (car.position.coordinates.0 + wheelbase / 2.0 * cos, car.position.coordinates.1 + wheelbase / 2.0 * sin);
+
+        // front axle may already be on the next road
+        let mut road = car.navigator.get_road();
+        let mut projection = road.project(front_axle);
+        if projection.0 > road.length() {
+            road = car.navigator.get_next_road();
+            projection = road.project(front_axle);
+        }
+        let (s, lateral) = projection;
+
+        let heading_error = normalize_angle(road.tangent_at(s) - car.position.orientation);
+        // positive lateral is to the left of the road, so the car has to turn right
+        let wheel_angle = (heading_error - (self.gain * lateral / (self.softening + car.speed.abs())).atan())
+            .clamp(-std::f32::consts::FRAC_PI_2 * 0.9, std::f32::consts::FRAC_PI_2 * 0.9);
+        let curvature = wheel_angle.tan() / wheelbase;
+
+        debug.desired_position = Some(road.get_position_at(s));
+        debug.angle_to_orientation = Some(heading_error);
+        debug.turning_angle = Some(wheel_angle);
+
+        let max_steer = car.params.max_steer;
+        let planned_speed = get_planned_speed(car, car.navigator.get_road(), car.navigator.get_progress());
+        follow_planned_speed(car, Gear::Drive, planned_speed, curvature.clamp(-max_steer, max_steer), debug)
+    }
+}
+
+/// Linear quadratic regulator of the distance from the road and the heading error.
+/// Measured along the road, both only depend on the curvature car drives with
+/// (`distance' = heading`, `heading' = curvature - road curvature`), so gains do not depend on speed.
+#[derive(Debug,Clone)]
+pub struct Lqr {
+    /// cost of the distance from the road, per pixel squared
+    pub lateral_weight: f32,
+    /// cost of the heading error, per radian squared
+    pub heading_weight: f32,
+    /// cost of curvature that differs from the road's
+    pub steering_weight: f32,
+    /// distance ahead in which changes of the road curvature are fed forward
+    pub lookahead: f32,
+}
+
+impl Default for Lqr {
+    fn default() -> Self {
+        Self { lateral_weight: 1e-6, heading_weight: 4e-3, steering_weight: 1.0, lookahead: DEFAULT_LOOKAHEAD }
+    }
+}
+
+impl Lqr {
+    /// Solution of the Riccati equation of a double integrator
+    fn get_gains(&self) -> (f32, f32) {
+        let lateral_gain = (self.lateral_weight / self.steering_weight).sqrt();
+        let heading_gain = (self.heading_weight / self.steering_weight + 2.0 * lateral_gain).sqrt();
+        (lateral_gain, heading_gain)
+    }
+}
+
+impl Controller for Lqr {
+    fn control(&mut self, car: &Observation, debug: &mut CarDebugInfo) -> Controls {
+        let road = car.navigator.get_road();
+        let progress = car.navigator.get_progress();
+        let (_, lateral) = road.project(car.position.coordinates);
+        let heading_error = normalize_angle(car.position.orientation - road.tangent_at(progress));
+
+        let (lateral_gain, heading_gain) = self.get_gains();
+        // regulator only corrects errors, turning into the next road is left to the feed-forward
+        let curvature = get_taget_steering(car, self.lookahead) - lateral_gain * lateral - heading_gain * heading_error;
+
+        debug.desired_position = Some(road.get_position_at(progress));
+        debug.angle_to_orientation = Some(-heading_error);
+        debug.curvature_profile = Some(get_curvature_profile(car, self.lookahead));
+
+        let max_steer = car.params.max_steer;
+        let planned_speed = get_planned_speed(car, road, progress);
+        follow_planned_speed(car, Gear::Drive, planned_speed, curvature.clamp(-max_steer, max_steer), debug)
+    }
+}
+
+
+/// Model predictive control: tries out a set of control sequences on a copy of the car,
+/// driven by the same physics, and takes the first step of the cheapest one.
+/// Each sequence follows the curvature of the road ahead, corrected by one steering offset
+/// for the first half of the horizon and another one for the second half, and holds
+/// one of the three pedals for the whole horizon. Every pair of `steering_offsets` is tried
+/// with every pedal, so each tick predicts `3 * steering_offsets.len()²` sequences.
+#[derive(Debug,Clone)]
+pub struct Mpc {
+    /// seconds of driving predicted ahead
+    pub horizon: f32,
+    /// physics updates per predicted second
+    pub steps_per_second: f32,
+    /// cost of the distance from the road, per pixel squared
+    pub lateral_weight: f32,
+    /// cost of the heading error, per radian squared
+    pub heading_weight: f32,
+    /// cost of the difference from the planned speed, per (pixel per second) squared
+    pub speed_weight: f32,
+    /// distance ahead in which changes of the road curvature are fed forward
+    pub lookahead: f32,
+    /// steering offsets tried out, as parts of the maximal steering
+    pub steering_offsets: Vec<f32>,
+}
+
+impl Default for Mpc {
+    fn default() -> Self {
+        Self { horizon: 1.0, steps_per_second: 10.0, lateral_weight: 1.0, heading_weight: 1e2, speed_weight: 0.1, lookahead: DEFAULT_LOOKAHEAD,
+               steering_offsets: MPC_STEERING_OFFSETS.to_vec() }
+    }
+}
+
+/// Steering offsets tried out by default: 243 predictions per tick. Small corrections are tried more finely,
+/// since they are what keeps the car on the road; coarser sets make it weave.
+const MPC_STEERING_OFFSETS: [f32; 9] = [-1.0, -0.4, -0.15, -0.05, 0.0, 0.05, 0.15, 0.4, 1.0];
+/// Going faster than planned costs that many times more than going slower
+const MPC_OVERSPEED_FACTOR: f32 = 10.0;
+
+/// What the driver does with the pedals during the whole horizon
+#[derive(Debug,Clone,Copy)]
+enum Pedal {
+    Brake,
+    Hold,
+    Accelerate,
+}
+
+impl Mpc {
+    fn get_controls(&self, car: &Observation, pedal: Pedal, steering_offset: f32) -> Controls {
+        let (desired_speed, brakes) = match pedal {
+            Pedal::Brake => (0.0, true),
+            Pedal::Hold => (car.speed.abs(), false),
+            Pedal::Accelerate => (car.cruise_speed, false),
+        };
+        let max_steer = car.params.max_steer;
+        let target_steering = get_taget_steering(car, self.lookahead);
+        let desired_steer = (target_steering + steering_offset * max_steer).clamp(-max_steer, max_steer);
+        Controls { desired_speed, desired_steer, brakes, desired_gear: Gear::Drive }
+    }
+
+    /// Cost of driving with `first` and then `second` steering offset and `pedal`, and where the car ends up
+    fn predict(&self, ghost: &mut Car, car: &Observation, pedal: Pedal, (first, second): (f32, f32), nearby_speed_limit: f32) -> (f32, Position) {
+        ghost.navigator = car.navigator.clone();
+        ghost.position = car.position.clone();
+        ghost.speed = car.speed;
+        ghost.steer = car.steer;
+        ghost.lateral_speed = car.lateral_speed;
+        ghost.yaw_rate = car.yaw_rate;
+        ghost.gear = car.gear;
+
+        let steps = (self.horizon * self.steps_per_second).ceil() as usize;
+        let dt = 1.0 / self.steps_per_second;
+        let mut cost = 0.0;
+        for step in 0..steps {
+            let controls = self.get_controls(&ghost.observe(&[], &[]), pedal, if step < steps / 2 { first } else { second });
+            ghost.desired_speed = controls.desired_speed;
+            ghost.desired_steer = controls.desired_steer;
+            ghost.brakes = controls.brakes;
+            ghost.desired_gear = controls.desired_gear;
+            ghost.update(dt);
+
+            let progress = ghost.navigator.track(ghost.position.coordinates);
+            let road = ghost.navigator.get_road();
+            let (_, lateral) = road.project(ghost.position.coordinates);
+            let heading_error = normalize_angle(ghost.position.orientation - road.tangent_at(progress));
+            let planned_speed = get_planned_speed(&ghost.observe(&[], &[]), road, progress).min(nearby_speed_limit);
+            let speed_error = ghost.speed - planned_speed;
+            let speed_cost = if speed_error > 0.0 { MPC_OVERSPEED_FACTOR } else { 1.0 } * speed_error.powi(2);
+
+            cost += self.lateral_weight * lateral.powi(2) + self.heading_weight * heading_error.powi(2) + self.speed_weight * speed_cost;
+        }
+        (cost, ghost.position.clone())
+    }
+}
+
+impl Controller for Mpc {
+    fn control(&mut self, car: &Observation, debug: &mut CarDebugInfo) -> Controls {
+        let mut ghost = Car::from_navigator(car.navigator.clone());
+        ghost.params = car.params.clone();
+        ghost.vehicle_model = car.vehicle_model.clone();
+        ghost.cruise_speed = car.cruise_speed;
+
+        // other cars are assumed to keep their distance, so they only limit the speed
+        let nearby_speed_limit = get_speed_limit_for_nearby_cars(car, Gear::Drive).min(get_speed_limit_for_stop_line(car)).min(get_speed_limit_for_slot(car));
+
+        let mut best: Option<(f32, Pedal, f32, Position)> = None;
+        for pedal in [Pedal::Brake, Pedal::Hold, Pedal::Accelerate] {
+            for &first in &self.steering_offsets {
+                for &second in &self.steering_offsets {
+                    let (cost, end) = self.predict(&mut ghost, car, pedal, (first, second), nearby_speed_limit);
+                    if best.as_ref().is_none_or(|(best_cost, ..)| cost < *best_cost) {
+                        best = Some((cost, pedal, first, end));
+                    }
+                }
+            }
+        }
+        let (_, pedal, steer, end) = best.expect("There is always something to try");
+
+        debug.desired_position = Some(end);
+        debug.curvature_profile = Some(get_curvature_profile(car, self.lookahead));
+        debug.planned_speed = Some(get_planned_speed(car, car.navigator.get_road(), car.navigator.get_progress()).min(nearby_speed_limit));
+        self.get_controls(car, pedal, steer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::algorithm::{Heuristic, Thinker};
+    use crate::model::{Road, RoadTurnDirection};
+    use crate::navigator::{Navigator, RoadMap, RoadNode};
+
+    /// Car at the start of a track with two straights and two half circles, driven by `controller`
+    fn car_on_track(controller: Box<dyn Controller>) -> Car {
+        let road_map = RoadMap::new(vec![
+            RoadNode { road: Road::Line { start: (0., 0.), end: (600., 0.) }, next: vec![1] },
+            RoadNode { road: Road::Turn { coordinates: (600., 150.), radius: 150., start_angle: -FRAC_PI_2, end_angle: FRAC_PI_2, direction: RoadTurnDirection::CCW }, next: vec![2] },
+            RoadNode { road: Road::Line { start: (600., 300.), end: (0., 300.) }, next: vec![3] },
+            RoadNode { road: Road::Turn { coordinates: (0., 150.), radius: 150., start_angle: FRAC_PI_2, end_angle: -FRAC_PI_2, direction: RoadTurnDirection::CCW }, next: vec![0] },
+        ]).expect("Should have created RoadMap");
+        let mut car = Car::from_navigator(Navigator::new(Rc::new(road_map), 0).expect("Should have created the navigator"));
+        car.controller = controller;
+        car
+    }
+
+    fn every_controller() -> [Box<dyn Controller>; 5] {
+        [
+            Box::<Heuristic>::default(),
+            Box::<PurePursuit>::default(),
+            Box::<Stanley>::default(),
+            Box::<Lqr>::default(),
+            Box::<Mpc>::default(),
+        ]
+    }
+
+    /// Largest distance from the road during a lap and a bit of driving around a track
+    fn worst_lateral_error(controller: Box<dyn Controller>) -> f32 {
+        let mut car = car_on_track(controller);
+
+        let mut worst = 0.0f32;
+        for _ in 0..15 * 60 {
+            car.think(&[], &[]);
+            car.update(1.0 / 60.0);
+            let (_, lateral) = car.navigator.get_road().project(car.position.coordinates);
+            worst = worst.max(lateral.abs());
+        }
+        worst
+    }
+
+    #[test]
+    fn every_controller_keeps_the_car_on_the_road() {
+        for controller in every_controller() {
+            let name = format!("{:?}", controller);
+            let error = worst_lateral_error(controller);
+            assert!(error < 8.0, "{name} got {error} px off the road");
+        }
+    }
+
+    #[test]
+    fn mpc_brakes_earlier_than_heuristic_before_sharp_turn() {
+        // where the car starts braking on its way from a long straight into a tight turn
+        let braking_point = |controller: Box<dyn Controller>| {
+            let road_map = RoadMap::new(vec![
+                RoadNode { road: Road::Line { start: (0., 0.), end: (1000., 0.) }, next: vec![1] },
+                RoadNode { road: Road::Turn { coordinates: (1000., 60.), radius: 60., start_angle: -FRAC_PI_2, end_angle: FRAC_PI_2, direction: RoadTurnDirection::CCW }, next: vec![2] },
+                RoadNode { road: Road::Line { start: (1000., 120.), end: (0., 120.) }, next: vec![0] },
+            ]).expect("Should have created RoadMap");
+            let mut car = Car::from_navigator(Navigator::new(Rc::new(road_map), 0).expect("Should have created the navigator"));
+            car.controller = controller;
+            car.speed = car.cruise_speed;
+            while car.navigator.get_road_id() == 0 {
+                car.think(&[], &[]);
+                if car.brakes { return car.navigator.get_progress(); }
+                car.update(1.0 / 60.0);
+            }
+            panic!("{:?} did not brake before the turn", car.controller)
+        };
+
+        // planned speed only drops at the heuristic's own position, MPC sees it dropping within its horizon
+        let (heuristic, mpc) = (braking_point(Box::<Heuristic>::default()), braking_point(Box::<Mpc>::default()));
+        assert!(mpc < heuristic - 50.0, "{mpc} {heuristic}");
+    }
+
+    #[test]
+    fn every_controller_turns_around() {
+        for controller in every_controller() {
+            let name = format!("{:?}", controller);
+            let mut car = car_on_track(controller);
+            car.position.coordinates = (300., 0.);
+            car.position.orientation = std::f32::consts::PI;
+
+            let mut reversed = false;
+            let mut drove_on = false;
+            for _ in 0..10 * 60 {
+                car.think(&[], &[]);
+                car.update(1.0 / 60.0);
+                reversed |= car.speed < 0.0;
+                drove_on |= car.gear == Gear::Drive && car.navigator.get_road_id() == 1;
+            }
+            assert!(reversed, "{name} never backed up");
+            // turned around and drove on to the next road of the track
+            assert!(drove_on, "{name} got stuck: {:?}", car.position);
+        }
+    }
+}
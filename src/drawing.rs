@@ -1,80 +1,130 @@
-use crate::{model::{Car, Road, Roundabout}, navigator::RoadMap};
+use driving_algorithm::model::DEFAULT_LANE_WIDTH;
+use driving_algorithm::{Car, LightState, Obstacle, Position, Priority, Road, RoadMap, Roundabout, Signal};
 use nannou::prelude::*;
 
 pub trait Drawing {
     fn draw(&self, draw: &Draw);
 }
 
-pub trait DrawingDebug {
-    fn draw_debug(&self, draw: &Draw);
+/// Draws the object between two ticks of simulation
+pub trait DrawingInterpolated {
+    fn draw_interpolated(&self, draw: &Draw, alpha: f32);
 }
 
+/// Draws what the object was thinking about, around where it is drawn between two ticks of simulation
+pub trait DrawingDebug {
+    fn draw_debug(&self, draw: &Draw, alpha: f32);
+}
 
-const STEER_FACTOR: f32 = 40.0;
 
 impl Drawing for Car {
     fn draw(&self, draw: &Draw) {
+        draw_car_at(self, &self.position, draw);
+    }
+}
 
-        let wheel_fl = get_coords_for_wheels(self.position.coordinates, self.position.orientation, ( 10., 9.));
-        let wheel_fr = get_coords_for_wheels(self.position.coordinates, self.position.orientation, ( 10.,-9.));
-        let wheel_bl = get_coords_for_wheels(self.position.coordinates, self.position.orientation, (-10., 9.));
-        let wheel_br = get_coords_for_wheels(self.position.coordinates, self.position.orientation, (-10.,-9.));
+impl DrawingInterpolated for Car {
+    fn draw_interpolated(&self, draw: &Draw, alpha: f32) {
+        draw_car_at(self, &self.get_interpolated_position(alpha), draw);
+    }
+}
 
-        draw.rect()
-            .width(7.0)
-            .height(4.0)
-            .x_y(wheel_fl.0, wheel_fl.1)
-            .rotate(self.position.orientation + self.steer * STEER_FACTOR)
-            .color(DARKSLATEGREY);
+fn draw_car_at(car: &Car, position: &Position, draw: &Draw) {
 
-        draw.rect()
-            .width(7.0)
-            .height(4.0)
-            .x_y(wheel_fr.0, wheel_fr.1)
-            .rotate(self.position.orientation + self.steer * STEER_FACTOR)
-            .color(DARKSLATEGREY);
+    let axle = car.vehicle_model.get_wheelbase(&car.params) / 2.;
+    let track = car.params.width * 0.45;
+    let wheel_fl = get_coords_for_wheels(position.coordinates, position.orientation, ( axle, track));
+    let wheel_fr = get_coords_for_wheels(position.coordinates, position.orientation, ( axle,-track));
+    let wheel_bl = get_coords_for_wheels(position.coordinates, position.orientation, (-axle, track));
+    let wheel_br = get_coords_for_wheels(position.coordinates, position.orientation, (-axle,-track));
 
-        draw.rect()
-            .width(7.0)
-            .height(4.0)
-            .x_y(wheel_bl.0, wheel_bl.1)
-            .rotate(self.position.orientation)
-            .color(DARKSLATEGREY);
+    draw.rect()
+        .width(7.0)
+        .height(4.0)
+        .x_y(wheel_fl.0, wheel_fl.1)
+        .rotate(position.orientation + car.get_wheel_angle())
+        .color(DARKSLATEGREY);
 
-        draw.rect()
-            .width(7.0)
-            .height(4.0)
-            .x_y(wheel_br.0, wheel_br.1)
-            .rotate(self.position.orientation)
-            .color(DARKSLATEGREY);
+    draw.rect()
+        .width(7.0)
+        .height(4.0)
+        .x_y(wheel_fr.0, wheel_fr.1)
+        .rotate(position.orientation + car.get_wheel_angle())
+        .color(DARKSLATEGREY);
 
-        draw.rect()
-            .width(40.0)
-            .height(20.0)
-            .rotate(self.position.orientation)
-            .x_y(self.position.coordinates.0, self.position.coordinates.1)
-            .color(STEELBLUE);
+    draw.rect()
+        .width(7.0)
+        .height(4.0)
+        .x_y(wheel_bl.0, wheel_bl.1)
+        .rotate(position.orientation)
+        .color(DARKSLATEGREY);
+
+    draw.rect()
+        .width(7.0)
+        .height(4.0)
+        .x_y(wheel_br.0, wheel_br.1)
+        .rotate(position.orientation)
+        .color(DARKSLATEGREY);
+
+    draw.rect()
+        .width(car.params.length)
+        .height(car.params.width)
+        .rotate(position.orientation)
+        .x_y(position.coordinates.0, position.coordinates.1)
+        .color(STEELBLUE);
 
-    }
 }
 
+/// Distance between the teeth of the curvature comb
+const CURVATURE_COMB_STEP: f32 = 10.0;
+/// Pixels of tooth per unit of curvature
+const CURVATURE_COMB_SCALE: f32 = 3000.0;
+
 impl DrawingDebug for Car {
-    fn draw_debug(&self, draw: &Draw) {
+    fn draw_debug(&self, draw: &Draw, alpha: f32) {
+        let position = self.get_interpolated_position(alpha);
         if let Some(desired) = &self.debug.desired_position {
             draw.line()
-                .start(pt2(self.position.coordinates.0, self.position.coordinates.1))
+                .start(pt2(position.coordinates.0, position.coordinates.1))
                 .end(pt2(desired.coordinates.0, desired.coordinates.1))
                 .weight(2.0)
                 .color(GAINSBORO);
 
             draw.rect()
-                .width(40.0)
-                .height(20.0)
+                .width(self.params.length)
+                .height(self.params.width)
                 .roll(desired.orientation)
                 .x_y(desired.coordinates.0, desired.coordinates.1)
                 .color(GAINSBORO);
         }
 
+        if let Some(profile) = &self.debug.curvature_profile {
+            // curvature comb: the sharper the road ahead turns, the longer the teeth
+            let length = profile.last().map_or(0.0, |(end, _)| *end);
+            for tooth in 0..(length / CURVATURE_COMB_STEP) as usize {
+                let distance = tooth as f32 * CURVATURE_COMB_STEP;
+                let curvature = profile.iter().rev()
+                    .find(|(start, _)| *start <= distance)
+                    .map_or(0.0, |(_, curvature)| *curvature);
+                let on_road = self.navigator.get_position_ahead(distance);
+                let (sin, cos) = on_road.orientation.sin_cos();
+                draw.line()
+                    .start(pt2(on_road.coordinates.0, on_road.coordinates.1))
+                    .end(pt2(on_road.coordinates.0 - sin * curvature * CURVATURE_COMB_SCALE,
+                             on_road.coordinates.1 + cos * curvature * CURVATURE_COMB_SCALE))
+                    .weight(1.0)
+                    .color(ORANGE);
+            }
+        }
+
+        if let Some(distance) = self.debug.leader_distance {
+            let leader = self.navigator.get_position_ahead(distance);
+            draw.ellipse()
+                .radius(5.0)
+                .x_y(leader.coordinates.0, leader.coordinates.1)
+                .color(DARKRED);
+        }
+
         if let Some(angle) = &self.debug.angle_to_position {
             let arrow_len = 30.0;
             draw.arrow()
@@ -82,9 +132,9 @@ impl DrawingDebug for Car {
                 .head_length(8.0)
                 .head_width(4.0)
                 .color(DARKRED)
-                .start(pt2(self.position.coordinates.0, self.position.coordinates.1))
-                .end(pt2(self.position.coordinates.0 + (self.position.orientation + angle).cos() * arrow_len, 
-                         self.position.coordinates.1 + (self.position.orientation + angle).sin() * arrow_len));
+                .start(pt2(position.coordinates.0, position.coordinates.1))
+                .end(pt2(position.coordinates.0 + (position.orientation + angle).cos() * arrow_len, 
+                         position.coordinates.1 + (position.orientation + angle).sin() * arrow_len));
 
         }
 
@@ -95,9 +145,9 @@ impl DrawingDebug for Car {
                 .head_length(8.0)
                 .head_width(4.0)
                 .color(DARKGREEN)
-                .start(pt2(self.position.coordinates.0, self.position.coordinates.1))
-                .end(pt2(self.position.coordinates.0 + (self.position.orientation + angle).cos() * arrow_len, 
-                         self.position.coordinates.1 + (self.position.orientation + angle).sin() * arrow_len));
+                .start(pt2(position.coordinates.0, position.coordinates.1))
+                .end(pt2(position.coordinates.0 + (position.orientation + angle).cos() * arrow_len, 
+                         position.coordinates.1 + (position.orientation + angle).sin() * arrow_len));
 
         }
 
@@ -108,9 +158,9 @@ impl DrawingDebug for Car {
                 .head_length(8.0)
                 .head_width(4.0)
                 .color(BLACK)
-                .start(pt2(self.position.coordinates.0, self.position.coordinates.1))
-                .end(pt2(self.position.coordinates.0 + (self.position.orientation + angle).cos() * arrow_len, 
-                         self.position.coordinates.1 + (self.position.orientation + angle).sin() * arrow_len));
+                .start(pt2(position.coordinates.0, position.coordinates.1))
+                .end(pt2(position.coordinates.0 + (position.orientation + angle).cos() * arrow_len, 
+                         position.coordinates.1 + (position.orientation + angle).sin() * arrow_len));
 
         }
     }
@@ -125,21 +175,102 @@ fn get_coords_for_wheels(origin: (f32, f32), rot: f32, shift: (f32, f32)) -> (f3
 
 impl Drawing for RoadMap {
     fn draw(&self, draw: &Draw) {
-        for road in self.get_roads() {
+        for (road, lane) in self.get_lanes() {
             road.draw(draw);
+            // line between lanes going the same way is drawn by the lane to the left of it
+            if lane.left.is_none() { draw_lane_marking(road, lane.width / 2., false, draw); }
+            draw_lane_marking(road, -lane.width / 2., lane.right.is_some(), draw);
+        }
+        for (id, (road, lane)) in self.get_lanes().enumerate() {
+            let dashed = match lane.priority {
+                Priority::Stop => false,
+                Priority::Yield => true,
+                Priority::Minor | Priority::Major => continue,
+            };
+            // line is where cars wait for their turn, at every merge the lane leads into
+            for zone in self.get_conflict_zones().iter().filter(|zone| zone.incoming.contains(&id)) {
+                draw_give_way_line(road, lane.width, road.length() - zone.get_waiting_distance(id), dashed, draw);
+            }
+        }
+    }
+}
+
+/// Line across the lane `distance` along the `road`, solid at a stop sign and dashed at a yield sign
+fn draw_give_way_line(road: &Road, width: f32, distance: f32, dashed: bool, draw: &Draw) {
+    let position = road.get_position_at(distance.max(0.));
+    let (sin, cos) = position.orientation.sin_cos();
+    let point = |lateral: f32| pt2(position.coordinates.0 - lateral * sin, position.coordinates.1 + lateral * cos);
+    let dashes = if dashed { (width / LANE_MARKING_STEP) as usize } else { 1 };
+    let dash = width / dashes as f32;
+    for i in (0..dashes).step_by(if dashed { 2 } else { 1 }) {
+        let left = width / 2. - i as f32 * dash;
+        draw.line().start(point(left)).end(point(left - dash)).weight(3.0).color(DIMGRAY);
+    }
+}
+
+/// Length of a dash of lane marking, and of the gap after it
+const LANE_MARKING_STEP: f32 = 10.0;
+
+/// Solid or dashed line running alongside `road`, `lateral` pixels to the left of it
+fn draw_lane_marking(road: &Road, lateral: f32, dashed: bool, draw: &Draw) {
+    let length = road.length();
+    let steps = (length / LANE_MARKING_STEP).ceil() as i32;
+    let points: Vec<Point2> = (0..=steps).map(|i| {
+        let position = road.get_position_at((i as f32 * LANE_MARKING_STEP).min(length));
+        let (sin, cos) = position.orientation.sin_cos();
+        pt2(position.coordinates.0 - lateral * sin, position.coordinates.1 + lateral * cos)
+    }).collect();
+
+    if dashed {
+        for dash in points.windows(2).step_by(2) {
+            draw.line().start(dash[0]).end(dash[1]).weight(1.0).color(DARKGRAY);
         }
+    } else {
+        draw.polyline().weight(1.0).color(DARKGRAY).points(points);
+    }
+}
+
+/// Stop lines at the ends of the lanes with traffic lights, in the color of their light
+pub fn draw_signals(map: &RoadMap, signals: &[Signal], draw: &Draw) {
+    for signal in signals {
+        let road = map.get_road_by_id(signal.road);
+        let half_width = map.get_lane(signal.road).width / 2.;
+        let end = road.get_position_at(road.length());
+        let (sin, cos) = end.orientation.sin_cos();
+        let color = match signal.state {
+            LightState::Green => GREEN,
+            LightState::Amber => ORANGE,
+            LightState::Red => RED,
+        };
+        draw.line()
+            .start(pt2(end.coordinates.0 - half_width * sin, end.coordinates.1 + half_width * cos))
+            .end(pt2(end.coordinates.0 + half_width * sin, end.coordinates.1 - half_width * cos))
+            .weight(4.0)
+            .color(color);
+    }
+}
+
+impl Drawing for Obstacle {
+    fn draw(&self, draw: &Draw) {
+        draw.rect()
+            .x_y(self.position.coordinates.0, self.position.coordinates.1)
+            .w_h(self.length, self.width)
+            .z_radians(self.position.orientation)
+            .color(DIMGRAY);
     }
 }
 
 impl Drawing for Roundabout {
+    /// Island in the middle, roads around it are drawn by the map
     fn draw(&self, draw: &Draw) {
+        let radius = self.radius - DEFAULT_LANE_WIDTH / 2.;
         draw.ellipse()
-            .w(self.radius * 2.)
-            .h(self.radius * 2.)
+            .w(radius * 2.)
+            .h(radius * 2.)
             .x_y(self.coordinates.0, self.coordinates.1)
             .stroke_weight(2.0)
             .stroke_color(GAINSBORO)
-            .no_fill();
+            .color(LIGHTGRAY);
 
     }
 }
@@ -147,19 +278,14 @@ impl Drawing for Roundabout {
 impl Drawing for Road {
     fn draw(&self, draw: &Draw) {
         match self {
-            Road::Turn { coordinates, radius, start_angle, end_angle, direction } => {
-                let (start_angle, end_angle) = match direction {
-                    crate::model::RoadTurnDirection::CCW => (start_angle, end_angle),
-                    crate::model::RoadTurnDirection::CW => (end_angle, start_angle)
-                };
-                let end_angle = if start_angle > end_angle { end_angle + PI * 2.0 } else { *end_angle };
-                let total_angle = end_angle - start_angle;
-                let angle_step = 5.0 * PI / radius;
-                let steps = (total_angle / angle_step).ceil() as i32;
+            Road::Turn { .. } => {
+                let length = self.length();
+                let step = 5.0 * PI;
+                let steps = (length / step).ceil() as i32;
                 let points = (0..=steps).map(|i| {
-                    let angle = start_angle + i as f32 * angle_step;
-                    pt2(coordinates.0 + radius * angle.cos(), coordinates.1 + radius * angle.sin())
-                }); 
+                    let (x, y) = self.point_at((i as f32 * step).min(length));
+                    pt2(x, y)
+                });
 
                 draw.polyline()
                     .weight(2.0)
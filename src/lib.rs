@@ -0,0 +1,28 @@
+//! Driving algorithm without machine learning, *just math*.
+//!
+//! The library holds the road model, the physics and the driving algorithm.
+//! Graphical viewer lives in the `driving-algorithm` binary, behind the
+//! `viewer` feature, so the library itself never depends on `nannou`.
+
+pub mod model;
+pub mod physics;
+pub mod algorithm;
+pub mod controllers;
+pub mod util;
+pub mod navigator;
+pub mod simulation;
+pub mod collision;
+pub mod safety;
+pub mod signals;
+pub mod reservations;
+pub mod telemetry;
+
+pub use model::{Car, CarDebugInfo, Gear, NearbyCar, Obstacle, Position, Road, RoadTurnDirection, Roundabout, TireModel, VehicleModel, VehicleParams};
+pub use navigator::{ConflictZone, Lane, Navigator, NavigatorCreationError, NavigatorRoutingError, Priority, RoadIndex, RoadMap, RoadMapError, RoadNode};
+pub use navigator::{road_nodes_from_toml, cars_from_toml, obstacles_from_toml, intersections_from_toml, reservation_managers_from_toml, RoadMapDeserializationError, FractionNotationError};
+pub use algorithm::{Controller, Controls, Heuristic, Observation, Thinker};
+pub use controllers::{Lqr, Mpc, PurePursuit, Stanley};
+pub use physics::Physics;
+pub use simulation::{Body, Simulation, SimulationEvent};
+pub use signals::{Intersection, LightState, Phase, PhasePlan, Signal};
+pub use reservations::{Reservation, ReservationManager, Slot};
@@ -1,96 +1,188 @@
-mod model;
+mod cli;
 mod drawing;
-mod physics;
-mod algorithm;
-mod util;
-mod navigator;
 
-use std::{rc::Rc, fs};
+use std::{cell::RefCell, fs, process};
 
-use algorithm::Thinker;
-use model::Car;
-use drawing::{Drawing, DrawingDebug};
-use physics::Physics;
-use navigator::{RoadMap,Navigator, road_nodes_from_toml};
+use cli::{Arguments, ArgumentsError, USAGE};
+use driving_algorithm::{Body, RoadMapDeserializationError, Simulation, SimulationEvent};
+use driving_algorithm::telemetry::TelemetryWriter;
+use drawing::{draw_signals, Drawing, DrawingDebug, DrawingInterpolated};
 
 use nannou::prelude::*;
 
 
+/// Longest real time one frame may take, in seconds
+const MAX_FRAME_TIME: f32 = 0.25;
+
+thread_local! {
+    /// nannou builds the model from a plain `fn`, so everything prepared in
+    /// `main` is handed over through here.
+    static PREPARED_MODEL: RefCell<Option<Model>> = const { RefCell::new(None) };
+}
+
 fn main() {
-    nannou::app(model)
-        .update(update)
-        .simple_window(view)
-        .run();
+    let arguments = Arguments::parse(std::env::args().skip(1)).unwrap_or_else(|error| {
+        match error {
+            ArgumentsError::HelpRequested => { println!("{USAGE}"); process::exit(0) },
+            ArgumentsError::UnknownArgument { argument } => eprintln!("unknown argument: `{argument}`"),
+            ArgumentsError::MissingValue { argument } => eprintln!("argument `{argument}` needs a value"),
+            ArgumentsError::InvalidValue { argument, value } => eprintln!("value '{value}' is not valid for `{argument}`"),
+            ArgumentsError::HeadlessWithoutTicks => eprintln!("headless simulation never ends by itself, please limit it with `--ticks`"),
+            ArgumentsError::HeadlessWithTimeScale => eprintln!("headless simulation runs as fast as it can, `--time-scale` has no effect there"),
+        }
+        eprintln!("\n{USAGE}");
+        process::exit(2);
+    });
+
+    let contents = fs::read_to_string(&arguments.map_path).unwrap_or_else(|error| {
+        eprintln!("Could not read map file `{}`: {error}", arguments.map_path.display());
+        process::exit(1);
+    });
+
+    let mut simulation = Simulation::from_toml(&contents).unwrap_or_else(|error| {
+        report_map_error(error);
+        process::exit(1);
+    });
+    simulation.set_updates_per_second(arguments.ups);
+
+    let telemetry = arguments.output.as_deref().map(TelemetryWriter::create).transpose().unwrap_or_else(|error| {
+        eprintln!("Could not write telemetry into `{}`: {error}", arguments.output.as_ref().unwrap().display());
+        process::exit(1);
+    });
+
+    let mut model = Model {
+        simulation,
+        telemetry,
+        debug: arguments.debug,
+        ticks: arguments.ticks,
+        time_scale: arguments.time_scale,
+        verbose: arguments.verbose,
+    };
+    // initial state is recorded too, as tick 0
+    model.record(&[]);
+
+    if arguments.headless {
+        while !model.is_finished() {
+            let events = model.simulation.step();
+            model.record(&events);
+        }
+        model.finish();
+    } else {
+        PREPARED_MODEL.with(|prepared| *prepared.borrow_mut() = Some(model));
+        nannou::app(take_prepared_model)
+            .update(update)
+            .simple_window(view)
+            .run();
+    }
+}
+
+fn report_map_error(error: RoadMapDeserializationError) {
+    use driving_algorithm::RoadMapDeserializationError::*;
+    eprint!("There was an error in the TOML file you provided as a map: ");
+    match error {
+        InvalidFormat { error } => eprintln!("TOML file has invalid format: {error}"),
+        MissingField { path } => eprintln!("missing field: `{path}`"),
+        InvalidFieldType { path } => eprintln!("field `{path}` is present, but it's value has a wrong type"),
+        UnknowRoadType { path, value } => eprintln!("road type you provided on `{path}` is unknown: '{value}'"),
+        InvalidFractionNotation { path, error } => {
+            use driving_algorithm::FractionNotationError::*;
+            eprint!("could not read fraction notation you provided on `{path}`: ");
+            match error {
+                DivisionSymbolAbsent => eprintln!("fraction notation doesnt contain the `/` symbol"),
+                InvalidNumerator => eprintln!("fraction notation has invalid numerator"),
+                InvalidDenominator => eprintln!("fraction notation has invalid denominator"),
+            }
+        },
+        InvalidDirectionNotation { path } => eprintln!("direction for road you provided on `{path}` is unknown"),
+        UnknownRoadReferece { reference } => eprintln!("detected reference for unknown road: '{reference}'"),
+        RoadMapError { error } => {
+            use driving_algorithm::RoadMapError::*;
+            eprint!("file was parsed successfully, but the road structure is invalid: ");
+            match error {
+                NoRoadsPresent => eprintln!("there are no roads in this map"),
+                DeadEndPresent => eprintln!("this map contains a dead end, every road should have a next one"),
+                NextIndexOutOfBounds => eprintln!("index of next road lies out of bounds of road list"),
+                LaneCountMismatch => eprintln!("number of lanes does not match the lanes declared for the roads, every lane needs exactly one declaration"),
+            }
+        },
+        OffsetOutOfRoad { path } => eprintln!("car offset you provided on `{path}` does not fit on the road"),
+        UnreachableDestination { path } => eprintln!("there is no way to drive to the destination you provided on `{path}`"),
+        UnknownVehicleModel { path, value } => eprintln!("vehicle model you provided on `{path}` is unknown: '{value}'"),
+        UnknownTireModel { path, value } => eprintln!("tire model you provided on `{path}` is unknown: '{value}'"),
+        UnknownVehicleType { path, value } => eprintln!("vehicle type you provided on `{path}` is unknown: '{value}'"),
+        UnknownController { path, value } => eprintln!("controller you provided on `{path}` is unknown: '{value}'"),
+        UnknownLaneDirection { path, value } => eprintln!("lane direction you provided on `{path}` is unknown: '{value}'"),
+        UnknownLane { path } => eprintln!("road does not have the lane you provided on `{path}`"),
+        UnknownPhasePlan { path, value } => eprintln!("phase plan you provided on `{path}` is unknown: '{value}'"),
+        UnknownPriority { path, value } => eprintln!("priority you provided on `{path}` is unknown: '{value}'"),
+        InvalidRoundaboutArm { path } => eprintln!("road you provided on `{path}` does not point at the roundabout from outside of it"),
+        DegenerateRoad { path } => eprintln!("road you provided on `{path}` can not be driven along: lines should not end where they start, and turns, their lanes and roundabouts need a radius"),
+        NotPositive { path } => eprintln!("value you provided on `{path}` should be greater than zero"),
+    }
 }
 
 struct Model {
-    map: Rc<RoadMap>,
-    cars: Vec<Car>,
-    debug: bool
+    simulation: Simulation,
+    telemetry: Option<TelemetryWriter>,
+    debug: bool,
+    /// simulation stops after that many ticks
+    ticks: Option<u64>,
+    time_scale: f32,
+    /// print every event
+    verbose: bool,
 }
 
-fn model(_app: &App) -> Model {
-
-    let contents = fs::read_to_string("resources/map.toml").expect("Could not find map file");
-
-    // TODO: Movce command line arguments, model initialization to fn main
-    let args: Vec<String> = std::env::args().collect(); // I hate to put it here...
-    let debug = args.iter().any(|s| s == "--debug");
-
-
-    let map = Rc::new(road_nodes_from_toml(&contents).map_err(|e| { 
-        use navigator::RoadMapDeserializationError::*; 
-        eprint!("There was an error in the TOML file you provided as a map: ");
-        match e {
-            InvalidFormat { error } => eprintln!("TOML file has invalid format: {error}"),
-            MissingField { path } => eprintln!("missing field: `{path}`"),
-            InvalidFieldType { path } => eprintln!("field `{path}` is present, but it's value has a wrong type"),
-            UnknowRoadType { path, value } => eprintln!("road type you provided on `{path}` is unknown: '{value}'"),
-            InvalidFractionNotation { path, error } => {
-                use navigator::FractionNotationError::*;
-                eprint!("could not read fraction notation you provided on `{path}`: ");
-                match error {
-                    DivisionSymbolAbsent => eprintln!("fraction notation doesnt contain the `/` symbol"),
-                    InvalidNumerator => eprintln!("fraction notation has invalid numerator"),
-                    InvalidDenominator => eprintln!("fraction notation has invalid denominator"),
-                }
-            },
-            InvalidDirectionNotation { path } => eprintln!("direction for road you provided on `{path}` is unknown"),
-            UnknownRoadReferece { reference } => eprintln!("while building graph structure, detected reference for unknown node: '{reference}'"),
-            RoadMapError { error } => {
-                use navigator::RoadMapError::*;
-                eprint!("file was parsed successfully, but the road structure is invalid: ");
-                match error {
-                    NoRoadsPresent => eprintln!("there are no roads in this map"),
-                    DeadEndPresent => eprintln!("this map contains a dead end, every road should have a next one"),
-                    NextIndexOutOfBounds => eprintln!("index of next road lies out of bounds of road list"),
-                }
-            },
+impl Model {
+    fn is_finished(&self) -> bool {
+        self.ticks.is_some_and(|ticks| self.simulation.get_tick() >= ticks)
+    }
+
+    fn record(&mut self, events: &[SimulationEvent]) {
+        if self.verbose {
+            events.iter().for_each(print_event);
+        }
+        if let Some(telemetry) = &mut self.telemetry {
+            if let Err(error) = telemetry.record(&self.simulation, events) {
+                eprintln!("Could not write telemetry, it will not be recorded anymore: {error}");
+                self.telemetry = None;
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(telemetry) = &mut self.telemetry {
+            if let Err(error) = telemetry.flush() {
+                eprintln!("Could not write telemetry: {error}");
+            }
+            self.telemetry = None;
         }
-        std::process::exit(1);
-    }).unwrap()); // FIXME: this unwrap is useless, will be gone when model building is moved to main
-
-    // TODO: also move car to map file, to load road situations from file
-    //
-    let cars: Vec<Car> = vec![{
-        let navigator = Navigator::new(Rc::clone(&map), 0).expect("Should have created the navigator");
-        let car = Car::from_navigator(navigator);
-        // car.position.coordinates = (80.0, -80.0);
-        car
-    }, {
-        let navigator = Navigator::new(Rc::clone(&map), 4).expect("Should have created the navigator");
-        let car = Car::from_navigator(navigator);
-        //car.position.coordinates = (-80.0, 80.0);
-        car
-    }];
-
-    Model { map, cars, debug }
+    }
 }
 
-fn update(_app: &App, model: &mut Model, _update: Update) {
-    for car in model.cars.iter_mut() {
-        car.think();
-        car.update();
+fn print_event(event: &SimulationEvent) {
+    match event {
+        SimulationEvent::Collision { tick, cars: (a, b) } => println!("tick {tick}: cars {a} and {b} collided"),
+        SimulationEvent::ObstacleCollision { tick, car, obstacle } => println!("tick {tick}: car {car} hit obstacle {obstacle}"),
+        SimulationEvent::EmergencyBraking { tick, car, other: Body::Car(other), time_to_collision } => println!("tick {tick}: car {car} braked {time_to_collision:.2} s before hitting car {other}"),
+        SimulationEvent::EmergencyBraking { tick, car, other: Body::Obstacle(obstacle), time_to_collision } => println!("tick {tick}: car {car} braked {time_to_collision:.2} s before hitting obstacle {obstacle}"),
+    }
+}
+
+fn take_prepared_model(_app: &App) -> Model {
+    PREPARED_MODEL.with(|prepared| prepared.borrow_mut().take()).expect("Model should have been prepared in main")
+}
+
+fn update(app: &App, model: &mut Model, update: Update) {
+    // after a long freeze of the window, do not try to catch up all at once
+    let elapsed = update.since_last.as_secs_f32().min(MAX_FRAME_TIME);
+    model.simulation.accumulate_time(elapsed * model.time_scale);
+    while !model.is_finished() {
+        let Some(events) = model.simulation.step_due() else { break };
+        model.record(&events);
+    }
+    if model.is_finished() {
+        model.finish();
+        app.quit();
     }
 }
 
@@ -100,11 +192,15 @@ fn view(app: &App, model: &Model, frame: Frame){
 
     draw.background().color(WHITESMOKE);
     
-    model.map.draw(&draw);
-
-    model.cars.iter().for_each(|car| {
-        if model.debug { car.draw_debug(&draw); }
-        car.draw(&draw);
+    model.simulation.get_map().get_roundabouts().iter().for_each(|roundabout| roundabout.draw(&draw));
+    model.simulation.get_map().draw(&draw);
+    draw_signals(model.simulation.get_map(), &model.simulation.get_signals(), &draw);
+    model.simulation.get_obstacles().iter().for_each(|obstacle| obstacle.draw(&draw));
+
+    let alpha = model.simulation.get_interpolation_factor();
+    model.simulation.get_cars().iter().for_each(|car| {
+        if model.debug { car.draw_debug(&draw, alpha); }
+        car.draw_interpolated(&draw, alpha);
     });
 
     // let window = app.main_window();
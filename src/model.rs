@@ -1,41 +1,233 @@
 use std::fmt::Debug;
-use std::f32::consts::FRAC_PI_2;
+use std::f32::consts::{FRAC_PI_2, PI};
 
-use crate::navigator::Navigator;
+use crate::algorithm::{Controller, Heuristic};
+use crate::navigator::{Navigator, RoadIndex};
+use crate::reservations::Slot;
+use crate::util::normalize_angle;
 
 
+/// Size of the body of an ordinary car
+pub const CAR_LENGTH: f32 = 40.0;
+pub const CAR_WIDTH: f32 = 20.0;
+/// Car is about four and a half meters long
+pub const PIXELS_PER_METER: f32 = CAR_LENGTH / 4.5;
+pub const GRAVITY: f32 = 9.81 * PIXELS_PER_METER;
+/// Front wheels of a point model car are drawn turned that many times its steering
+const POINT_MODEL_WHEEL_FACTOR: f32 = 40.0;
+/// Speed car keeps when there are no turns ahead, unless told otherwise
+pub const DEFAULT_CRUISE_SPEED: f32 = 170.0;
+/// Width of a lane, unless map says otherwise
+pub const DEFAULT_LANE_WIDTH: f32 = 40.0;
 
-#[derive(Debug)]
+#[derive(Debug,Clone)]
 pub struct Position {
     pub coordinates: (f32,f32),
     pub orientation: f32
 }
 
+/// How car movement follows from its speed and steering
+#[derive(Debug,Clone,Default)]
+pub enum VehicleModel {
+    /// Car turns `steer` radians per pixel driven, around its own center
+    #[default]
+    Point,
+    /// Kinematic bicycle: car turns around its rear axle, by the angle front wheels
+    /// are turned to. `steer` is still the curvature rear axle drives along, but it
+    /// changes as fast as the steering wheel can be turned, which is `steering_ratio`
+    /// times the angle of the front wheels.
+    Bicycle { wheelbase: f32, steering_ratio: f32 },
+    /// Dynamic bicycle: steered the same way as the kinematic one, but the car only
+    /// turns as much as its tires can push it sideways. Tires slip when asked for more
+    /// than `friction` allows; `front_stiffness` and `rear_stiffness` (per radian of slip)
+    /// decide whether the car understeers or oversteers.
+    Dynamic { wheelbase: f32, steering_ratio: f32, friction: f32, front_stiffness: f32, rear_stiffness: f32, tire: TireModel },
+}
+
+impl VehicleModel {
+    /// Distance between the axles, point model gets one from the size of the body
+    pub fn get_wheelbase(&self, params: &VehicleParams) -> f32 {
+        match self {
+            VehicleModel::Point => params.length / 2.0,
+            VehicleModel::Bicycle { wheelbase, .. } |
+            VehicleModel::Dynamic { wheelbase, .. } => *wheelbase,
+        }
+    }
+    /// How fast `steer` can change, per second. Bicycle models turn the steering wheel instead,
+    /// which changes the curvature the slowest when the wheels are straight.
+    pub fn get_steer_speed(&self, params: &VehicleParams) -> f32 {
+        match self {
+            VehicleModel::Point => params.steer_speed,
+            VehicleModel::Bicycle { wheelbase, steering_ratio } |
+            VehicleModel::Dynamic { wheelbase, steering_ratio, .. } => params.steering_wheel_speed / steering_ratio / wheelbase,
+        }
+    }
+    /// Sideways acceleration tires can hold before they start to slip, if they can slip at all
+    pub fn get_max_lateral_acceleration(&self) -> Option<f32> {
+        match self {
+            VehicleModel::Point | VehicleModel::Bicycle { .. } => None,
+            VehicleModel::Dynamic { friction, .. } => Some(friction * GRAVITY),
+        }
+    }
+}
+
+/// Physical abilities of a vehicle, so that different vehicles can share the road
+#[derive(Debug,Clone)]
+pub struct VehicleParams {
+    /// size of the body
+    pub length: f32,
+    pub width: f32,
+    /// deceleration with brakes on, pixels per second squared
+    pub brakes: f32,
+    /// how fast speed follows the pedal, pixels per second squared
+    pub acceleration: f32,
+    /// how fast steering of the point model changes, per second
+    pub steer_speed: f32,
+    pub max_steer: f32,
+    pub max_speed: f32,
+    pub max_reverse_speed: f32,
+    /// how fast the steering wheel of bicycle models turns, radians per second
+    pub steering_wheel_speed: f32,
+    /// radians the steering wheel of bicycle models turns from center to the lock
+    pub max_steering_wheel_angle: f32,
+}
+
+impl Default for VehicleParams {
+    fn default() -> Self {
+        Self {
+            length: CAR_LENGTH,
+            width: CAR_WIDTH,
+            brakes: 130.0,
+            acceleration: 40.0,
+            steer_speed: 0.025, // 0.03 for low speed // 0.01 for high speed
+            max_steer: 0.02,
+            max_speed: 200.0,
+            max_reverse_speed: 50.0,
+            steering_wheel_speed: 7.5,
+            // two and a half turns lock to lock
+            max_steering_wheel_angle: 2.5 * PI,
+        }
+    }
+}
+
+impl VehicleParams {
+    /// Length and width of the body
+    pub fn get_size(&self) -> (f32, f32) {
+        (self.length, self.width)
+    }
+    pub fn car() -> Self {
+        Self::default()
+    }
+    pub fn sports_car() -> Self {
+        Self { length: 40.0, width: 19.0, brakes: 180.0, acceleration: 90.0, steer_speed: 0.035, max_steer: 0.025, max_speed: 280.0, ..Self::default() }
+    }
+    pub fn truck() -> Self {
+        Self { length: 90.0, width: 26.0, brakes: 80.0, acceleration: 18.0, steer_speed: 0.015, max_steer: 0.012, max_speed: 140.0, max_reverse_speed: 30.0,
+               steering_wheel_speed: 5.0, ..Self::default() }
+    }
+    pub fn bus() -> Self {
+        Self { length: 105.0, width: 25.0, brakes: 90.0, acceleration: 20.0, steer_speed: 0.015, max_steer: 0.013, max_speed: 130.0, max_reverse_speed: 30.0,
+               steering_wheel_speed: 5.0, ..Self::default() }
+    }
+}
+
+/// Which way the engine drives the car
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+pub enum Gear {
+    #[default]
+    Drive,
+    /// engine is disconnected, car only rolls
+    Neutral,
+    Reverse,
+}
+
+/// Sideways force of a tire as a function of its slip angle
+#[derive(Debug,Clone)]
+pub enum TireModel {
+    /// Force grows with the slip angle until it hits the friction limit
+    Linear,
+    /// Pacejka's magic formula, `shape` is its C and `curvature` its E coefficient
+    Pacejka { shape: f32, curvature: f32 },
+}
+
 #[derive(Debug,Default)]
 pub struct CarDebugInfo {
     pub desired_position: Option<Position>,
     pub angle_to_position: Option<f32>,
     pub angle_to_orientation: Option<f32>,
     pub turning_angle: Option<f32>,
+    pub planned_speed: Option<f32>,
+    /// how far ahead along the route the car in front is
+    pub leader_distance: Option<f32>,
+    /// space between the bumpers of this car and the car in front
+    pub gap: Option<f32>,
+    /// how fast the gap closes, negative when it grows
+    pub approach_rate: Option<f32>,
+    /// pairs of distance ahead and curvature of the road from there on,
+    /// the last one is where controller stopped looking
+    pub curvature_profile: Option<Vec<(f32, f32)>>,
 }
 
 #[derive(Debug)]
 pub struct Car {
 
     pub brakes: bool,
+    /// how fast the car should go, in the direction of `gear`
     pub desired_speed: f32,
+    /// gear is only changed once the car stands still
+    pub desired_gear: Gear,
     pub desired_steer: f32,
+    pub cruise_speed: f32,
 
+    /// negative when the car moves backwards
     pub speed: f32, 
     pub steer: f32,
+    pub gear: Gear,
+    pub params: VehicleParams,
+    pub vehicle_model: VehicleModel,
+    /// speed to the left of the car, only dynamic model lets car slide sideways
+    pub lateral_speed: f32,
+    /// radians per second car turns with, only tracked by dynamic model
+    pub yaw_rate: f32,
     
     pub position: Position,
+    /// where the car was before the last tick, to draw it between ticks
+    pub previous_position: Position,
 
     pub navigator: Navigator,
+    pub controller: Box<dyn Controller>,
+    /// turn to drive through an intersection, given by its reservation manager
+    pub slot: Option<Slot>,
 
     pub debug: CarDebugInfo,
 }
 
+/// What a car can see about another car around it
+#[derive(Debug,Clone)]
+pub struct NearbyCar {
+    pub position: Position,
+    pub speed: f32,
+    /// How sharply it turns, same as `Car::steer`
+    pub steer: f32,
+    pub length: f32,
+    pub width: f32,
+}
+
+/// Something standing on the road, which is not a car
+#[derive(Debug,Clone)]
+pub struct Obstacle {
+    pub position: Position,
+    pub length: f32,
+    pub width: f32,
+}
+
+impl Obstacle {
+    /// Cars see an obstacle as a car that does not move
+    pub fn as_nearby_car(&self) -> NearbyCar {
+        NearbyCar { position: self.position.clone(), speed: 0.0, steer: 0.0, length: self.length, width: self.width }
+    }
+}
+
 impl Car {
     pub fn from_navigator(navigator: Navigator) -> Self {
         let start = navigator.get_road().get_start();
@@ -44,28 +236,108 @@ impl Car {
             brakes: false,
             desired_speed: 0.0,
             desired_steer: 0.0,
+            desired_gear: Gear::Drive,
+            cruise_speed: DEFAULT_CRUISE_SPEED,
             navigator,
+            controller: Box::<Heuristic>::default(),
+            slot: None,
 
             speed: 0.0,
             steer: 0.0,
+            gear: Gear::Drive,
+            params: VehicleParams::default(),
+            vehicle_model: VehicleModel::Point,
+            lateral_speed: 0.0,
+            yaw_rate: 0.0,
 
+            previous_position: start.clone(),
             position: start,
 
             debug: Default::default()
         }
     }
+    /// Position between the previous and the current tick, `alpha` of 0 is the previous one
+    pub fn get_interpolated_position(&self, alpha: f32) -> Position {
+        let (from, to) = (&self.previous_position, &self.position);
+        Position {
+            coordinates: (
+                from.coordinates.0 + (to.coordinates.0 - from.coordinates.0) * alpha,
+                from.coordinates.1 + (to.coordinates.1 - from.coordinates.1) * alpha,
+            ),
+            orientation: from.orientation + normalize_angle(to.orientation - from.orientation) * alpha,
+        }
+    }
+    /// Angle front wheels are turned to, relative to the car. Only the bicycle
+    /// model has real wheels, point model gets an exaggerated one to be visible.
+    pub fn get_wheel_angle(&self) -> f32 {
+        match self.vehicle_model {
+            VehicleModel::Point => self.steer * POINT_MODEL_WHEEL_FACTOR,
+            VehicleModel::Bicycle { wheelbase, .. } |
+            VehicleModel::Dynamic { wheelbase, .. } => (self.steer * wheelbase).atan(),
+        }
+    }
+    pub fn as_nearby_car(&self) -> NearbyCar {
+        NearbyCar { position: self.position.clone(), speed: self.speed, steer: self.steer, length: self.params.length, width: self.params.width }
+    }
 }
 
 
+/// Circle driven counter-clockwise, joined by roads that turn right into it and out of it
+#[derive(Debug,Clone)]
 pub struct Roundabout {
     pub coordinates: (f32, f32),
-    pub radius: f32
+    pub radius: f32,
+    /// roads leading onto the circle, cars on them give way to the cars on the circle
+    pub entries: Vec<RoadIndex>,
+    /// roads going around the circle
+    pub ring: Vec<RoadIndex>,
 }
 
+impl Roundabout {
+    /// Turn to the right from the `end` of a road onto the circle. `None` unless `end` is outside of the circle, facing it.
+    pub fn get_entry(&self, end: &Position) -> Option<Road> {
+        let (center, radius) = self.get_connecting_circle(end)?;
+        let angle_of = |point: (f32, f32)| (point.1 - center.1).atan2(point.0 - center.0);
+        let entry = Road::Turn { coordinates: center, radius, start_angle: angle_of(end.coordinates), end_angle: angle_of(self.coordinates), direction: RoadTurnDirection::CW };
+        (entry.length() / radius < PI).then_some(entry)
+    }
+
+    /// Turn to the right from the circle to the `start` of a road. `None` unless `start` is outside of the circle, facing away from it.
+    pub fn get_exit(&self, start: &Position) -> Option<Road> {
+        let (center, radius) = self.get_connecting_circle(start)?;
+        let angle_of = |point: (f32, f32)| (point.1 - center.1).atan2(point.0 - center.0);
+        let exit = Road::Turn { coordinates: center, radius, start_angle: angle_of(self.coordinates), end_angle: angle_of(start.coordinates), direction: RoadTurnDirection::CW };
+        (exit.length() / radius < PI).then_some(exit)
+    }
 
+    /// Angle from the center to the point where `road`, an entry or an exit, touches the circle.
+    /// `None` for a line, which is neither.
+    pub fn get_angle_of(&self, road: &Road) -> Option<f32> {
+        match road {
+            Road::Turn { coordinates, .. } => Some((coordinates.1 - self.coordinates.1).atan2(coordinates.0 - self.coordinates.0)),
+            Road::Line { .. } => None,
+        }
+    }
 
+    /// Circle on the right of a car at `position`, touching both the line it drives along and the roundabout from outside
+    fn get_connecting_circle(&self, position: &Position) -> Option<((f32, f32), f32)> {
+        let (sin, cos) = position.orientation.sin_cos();
+        let right = (sin, -cos);
+        let from_center = (position.coordinates.0 - self.coordinates.0, position.coordinates.1 - self.coordinates.1);
+        let distance_squared = from_center.0.powi(2) + from_center.1.powi(2);
+        let radius = (distance_squared - self.radius.powi(2)) / (2.0 * (self.radius - from_center.0 * right.0 - from_center.1 * right.1));
+        (distance_squared > self.radius.powi(2) && radius.is_finite() && radius > 0.0).then_some((
+            (position.coordinates.0 + radius * right.0, position.coordinates.1 + radius * right.1),
+            radius,
+        ))
+    }
+}
 
-#[derive(Debug)]
+
+
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug,Clone,Copy,PartialEq)]
 pub enum RoadTurnDirection {
     CW, CCW
 }
@@ -78,31 +350,218 @@ pub enum Road {
 
 impl Road {
     pub fn get_start(&self) -> Position {
+        self.get_position_at(0.0)
+    }
+    pub fn get_end(&self) -> (f32,f32) {
+        self.point_at(self.length())
+    }
+    /// Position `s` pixels along the road from its start, facing the way road goes
+    pub fn get_position_at(&self, s: f32) -> Position {
+        Position { coordinates: self.point_at(s), orientation: self.tangent_at(s) }
+    }
+
+    pub fn length(&self) -> f32 {
+        match self {
+            Road::Turn { radius, .. } => radius * self.get_sweep_angle(),
+            Road::Line { start, end } => {
+                ((end.0 - start.0).powi(2) + (end.1 - start.1).powi(2)).sqrt()
+            },
+        }
+    }
+
+    /// Point `s` pixels along the road. Values outside of `0..=length()` continue the road
+    /// beyond its ends: line goes on straight, turn goes on around the circle.
+    pub fn point_at(&self, s: f32) -> (f32, f32) {
         match self {
             Road::Turn { coordinates, radius, start_angle, direction, .. } => {
-                let x = coordinates.0 + radius * start_angle.cos();
-                let y = coordinates.1 + radius * start_angle.sin();
-                let a = start_angle + match direction {
-                    RoadTurnDirection::CW => -FRAC_PI_2,
-                    RoadTurnDirection::CCW => FRAC_PI_2,
-                };
-                Position { coordinates: (x, y), orientation: a }
+                let angle = get_turn_angle_at(*start_angle, *radius, *direction, s);
+                (coordinates.0 + radius * angle.cos(), coordinates.1 + radius * angle.sin())
             },
             Road::Line { start, end } => {
-                Position { coordinates: *start, orientation: (end.1 - start.1).atan2(end.0 - start.0) }
+                let t = s / self.length();
+                (start.0 + (end.0 - start.0) * t, start.1 + (end.1 - start.1) * t)
             },
         }
-        
     }
-    pub fn get_end(&self) -> (f32,f32) {
+
+    /// Direction the road goes `s` pixels along it
+    pub fn tangent_at(&self, s: f32) -> f32 {
+        match self {
+            Road::Turn { radius, start_angle, direction, .. } => normalize_angle(get_turn_angle_at(*start_angle, *radius, *direction, s) + match direction {
+                RoadTurnDirection::CW => -FRAC_PI_2,
+                RoadTurnDirection::CCW => FRAC_PI_2,
+            }),
+            Road::Line { start, end } => (end.1 - start.1).atan2(end.0 - start.0),
+        }
+    }
+
+    /// Radians of heading change per pixel driven along the road, positive to the left.
+    /// Same units as car steering.
+    pub fn curvature_at(&self, _s: f32) -> f32 {
+        match self {
+            Road::Turn { radius, direction, .. } => match direction {
+                RoadTurnDirection::CW => -1.0 / radius,
+                RoadTurnDirection::CCW => 1.0 / radius,
+            },
+            Road::Line { .. } => 0.0,
+        }
+    }
+
+    /// Finds the point of the road closest to `point`. Returns how far along the road
+    /// it is, and how far `point` is from it, positive to the left of the road.
+    /// Points off the ends of the road project beyond `0..=length()`; for a turn,
+    /// onto whichever end is closer around the circle.
+    pub fn project(&self, point: (f32, f32)) -> (f32, f32) {
         match self {
-            Road::Turn { coordinates, radius, end_angle, .. } => {
-                (coordinates.0 + radius * end_angle.cos(), coordinates.1 + radius * end_angle.sin())
+            Road::Turn { coordinates, radius, start_angle, direction, .. } => {
+                let rel = (point.0 - coordinates.0, point.1 - coordinates.1);
+                let angle = rel.1.atan2(rel.0);
+                let distance_from_center = (rel.0 * rel.0 + rel.1 * rel.1).sqrt();
+                let (swept, lateral) = match direction {
+                    RoadTurnDirection::CW  => ((start_angle - angle).rem_euclid(2.0 * PI), distance_from_center - radius),
+                    RoadTurnDirection::CCW => ((angle - start_angle).rem_euclid(2.0 * PI), radius - distance_from_center),
+                };
+                let sweep = self.get_sweep_angle();
+                // outside of the arc, the first half of the gap lies past the end
+                let swept = if swept > sweep && swept - sweep > 2.0 * PI - swept { swept - 2.0 * PI } else { swept };
+                (swept * radius, lateral)
+            },
+            Road::Line { start, end } => {
+                let length = self.length();
+                let direction = ((end.0 - start.0) / length, (end.1 - start.1) / length);
+                let rel = (point.0 - start.0, point.1 - start.1);
+                (rel.0 * direction.0 + rel.1 * direction.1, direction.0 * rel.1 - direction.1 * rel.0)
+            },
+        }
+    }
+
+    /// Road running alongside this one, `lateral` pixels to the left of it.
+    /// Turn offset past its center ends up with a radius that is not positive, map loader refuses such lanes.
+    pub fn offset(&self, lateral: f32) -> Road {
+        match *self {
+            Road::Turn { coordinates, radius, start_angle, end_angle, direction } => {
+                // left of a counter-clockwise turn is towards its center
+                let radius = match direction {
+                    RoadTurnDirection::CW => radius + lateral,
+                    RoadTurnDirection::CCW => radius - lateral,
+                };
+                Road::Turn { coordinates, radius, start_angle, end_angle, direction }
+            },
+            Road::Line { start, end } => {
+                let length = self.length();
+                let normal = (-(end.1 - start.1) / length * lateral, (end.0 - start.0) / length * lateral);
+                Road::Line { start: (start.0 + normal.0, start.1 + normal.1), end: (end.0 + normal.0, end.1 + normal.1) }
             },
-            Road::Line { end, .. } => {
-                *end
+        }
+    }
+
+    /// Same road, driven the other way
+    pub fn reversed(&self) -> Road {
+        match *self {
+            Road::Turn { coordinates, radius, start_angle, end_angle, direction } => {
+                let direction = match direction {
+                    RoadTurnDirection::CW => RoadTurnDirection::CCW,
+                    RoadTurnDirection::CCW => RoadTurnDirection::CW,
+                };
+                Road::Turn { coordinates, radius, start_angle: end_angle, end_angle: start_angle, direction }
             },
+            Road::Line { start, end } => Road::Line { start: end, end: start },
+        }
+    }
+
+    /// How many radians turn goes around its center
+    fn get_sweep_angle(&self) -> f32 {
+        match self {
+            Road::Turn { start_angle, end_angle, direction, .. } => {
+                let sweep = match direction {
+                    RoadTurnDirection::CW => (start_angle - end_angle).rem_euclid(2.0 * PI),
+                    RoadTurnDirection::CCW => (end_angle - start_angle).rem_euclid(2.0 * PI),
+                };
+                // turn that ends where it starts is a full circle
+                if sweep == 0.0 { 2.0 * PI } else { sweep }
+            },
+            Road::Line { .. } => 0.0,
         }
     }
 }
 
+/// Angle from the center of a turn to the point `s` pixels along it
+fn get_turn_angle_at(start_angle: f32, radius: f32, direction: RoadTurnDirection, s: f32) -> f32 {
+    start_angle + match direction {
+        RoadTurnDirection::CW => -s / radius,
+        RoadTurnDirection::CCW => s / radius,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_4;
+
+    use super::*;
+
+    fn assert_close(a: (f32, f32), b: (f32, f32)) {
+        assert!((a.0 - b.0).abs() < 1e-3 && (a.1 - b.1).abs() < 1e-3, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn line_geometry() {
+        let road = Road::Line { start: (0., 0.), end: (30., 40.) };
+        assert_eq!(road.length(), 50.);
+        assert_close(road.point_at(25.), (15., 20.));
+        assert_eq!(road.curvature_at(10.), 0.);
+        // 10 to the left of the middle of the road
+        assert_close(road.project((15. - 8., 20. + 6.)), (25., 10.));
+        assert_close(road.project((-3., -4.)), (-5., 0.));
+    }
+
+    #[test]
+    fn turn_geometry() {
+        // quarter of a circle from the bottom to the left, clockwise
+        let road = Road::Turn { coordinates: (0., 0.), radius: 10., start_angle: -FRAC_PI_2, end_angle: PI, direction: RoadTurnDirection::CW };
+        assert!((road.length() - 5. * PI).abs() < 1e-4);
+        assert_close(road.point_at(road.length() / 2.), (-10. * FRAC_PI_4.cos(), -10. * FRAC_PI_4.sin()));
+        assert!((road.tangent_at(0.) - PI).abs() < 1e-4 || (road.tangent_at(0.) + PI).abs() < 1e-4);
+        assert_eq!(road.curvature_at(0.), -0.1);
+        // outside of the circle is to the left of a clockwise turn
+        let (s, lateral) = road.project((0., -12.));
+        assert_close((s, lateral), (0., 2.));
+        // point just past the end, and just before the start
+        assert!(road.project((-10., 1.)).0 > road.length());
+        assert!(road.project((1., -10.)).0 < 0.);
+    }
+
+    #[test]
+    fn point_and_projection_agree() {
+        let road = Road::Turn { coordinates: (5., -5.), radius: 20., start_angle: 3.0, end_angle: -2.5, direction: RoadTurnDirection::CCW };
+        for i in 0..=10 {
+            let s = road.length() * i as f32 / 10.;
+            let (projected, lateral) = road.project(road.point_at(s));
+            assert_close((projected, lateral), (s, 0.));
+        }
+    }
+
+    #[test]
+    fn lanes_run_alongside_road() {
+        let line = Road::Line { start: (0., 0.), end: (100., 0.) };
+        let turn = Road::Turn { coordinates: (0., 0.), radius: 50., start_angle: -FRAC_PI_2, end_angle: 0., direction: RoadTurnDirection::CCW };
+        for road in [line, turn] {
+            for s in [0., 20., 40.] {
+                let (_, lateral) = road.project(road.offset(10.).point_at(s));
+                assert!((lateral - 10.).abs() < 1e-3, "{lateral}");
+                let reversed = road.reversed();
+                assert_close(reversed.point_at(reversed.length() - s), road.point_at(s));
+            }
+        }
+    }
+
+    #[test]
+    fn roundabout_entry_touches_the_circle() {
+        let roundabout = Roundabout { coordinates: (0., 0.), radius: 100., entries: Vec::new(), ring: Vec::new() };
+        let entry = roundabout.get_entry(&Position { coordinates: (-40., 300.), orientation: -FRAC_PI_2 }).expect("Road ends facing the circle");
+        let joint = roundabout.get_angle_of(&entry).expect("Entry is a turn");
+        assert_close(entry.get_end(), (100. * joint.cos(), 100. * joint.sin()));
+        // lines do not join the circle
+        assert_eq!(roundabout.get_angle_of(&Road::Line { start: (-40., 300.), end: (-40., 100.) }), None);
+    }
+}
@@ -1,58 +1,256 @@
-use std::{rc::Rc, collections::HashMap};
+use std::{rc::Rc, collections::{HashMap, VecDeque}, f32::consts::PI};
 
 mod map {
-    use crate::model::Road;
+    use std::cmp::Ordering;
+    use std::collections::{BinaryHeap, HashMap};
+
+    use crate::model::{Road, Roundabout, DEFAULT_LANE_WIDTH};
 
     pub type RoadIndex = usize;
 
+    /// Lanes that change into one another are this many pixels longer to drive, for the route planner
+    const LANE_CHANGE_COST: f32 = 100.0;
+
+    /// Every lane is a road of its own, running alongside the other lanes of the same road
     #[derive(Debug)]
     pub struct RoadNode { pub road: Road, pub next: Vec<RoadIndex>}
 
+    /// Where a road node lies across the road of the map file it was made from
+    #[derive(Debug,Clone)]
+    pub struct Lane {
+        /// key of the road in the map file
+        pub road: String,
+        /// place among the lanes of that road, counting from the left
+        pub index: usize,
+        pub width: f32,
+        /// neighbouring lanes going the same way, car can change into them
+        pub left: Option<RoadIndex>,
+        pub right: Option<RoadIndex>,
+        pub priority: Priority,
+    }
+
+    /// Right of way of a road at its end, where it merges with other roads. Cars give way to the cars
+    /// coming from roads with a higher priority, and let the first one to arrive go among equals.
+    #[derive(Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord,Default)]
+    pub enum Priority {
+        /// car stops at the end of the road even if nobody is coming
+        Stop,
+        Yield,
+        Minor,
+        #[default]
+        Major,
+    }
+
+    /// Place where several roads lead into the same one, cars coming from them may collide there
+    #[derive(Debug,Clone)]
+    pub struct ConflictZone {
+        /// road the others lead into, zone is at its start
+        pub road: RoadIndex,
+        pub incoming: Vec<RoadIndex>,
+        /// how far before the end of each of the `incoming` roads its lane gets as close to the others as lanes
+        /// running alongside, cars waiting for their turn wait before that
+        pub waiting_distances: Vec<f32>,
+    }
+
+    impl ConflictZone {
+        pub fn get_waiting_distance(&self, road: RoadIndex) -> f32 {
+            self.incoming.iter().position(|incoming| *incoming == road).map_or(0.0, |i| self.waiting_distances[i])
+        }
+    }
+
+    /// How finely the edge of a conflict zone is looked for, in pixels along the road
+    const WAITING_DISTANCE_STEP: f32 = 1.0;
+
     #[derive(Debug)]
     pub struct RoadMap {
         nodes: Vec<RoadNode>,
+        lanes: Vec<Lane>,
+        /// keys roads had in the map file, each leads to the rightmost lane going forward
+        keys: HashMap<String, RoadIndex>,
+        roundabouts: Vec<Roundabout>,
+        previous: Vec<Vec<RoadIndex>>,
+        conflict_zones: Vec<ConflictZone>,
     }
     #[derive(Debug)]
     pub enum RoadMapError {
         NoRoadsPresent,
         DeadEndPresent,
-        NextIndexOutOfBounds
+        NextIndexOutOfBounds,
+        /// Every road node is a lane, and should have exactly one `Lane` declared for it
+        LaneCountMismatch,
     }
 
     impl RoadMap {
+        /// Roads are keyed by their index and have a single lane
         pub fn new(nodes: Vec<RoadNode>) -> Result<Self, RoadMapError> {
+            let keys = (0..nodes.len()).map(|i| (i.to_string(), i)).collect();
+            let lanes = (0..nodes.len())
+                .map(|i| Lane { road: i.to_string(), index: 0, width: DEFAULT_LANE_WIDTH, left: None, right: None, priority: Priority::default() })
+                .collect();
+            Self::new_with_lanes(nodes, lanes, keys)
+        }
+        pub fn new_with_lanes(nodes: Vec<RoadNode>, lanes: Vec<Lane>, keys: HashMap<String, RoadIndex>) -> Result<Self, RoadMapError> {
             if nodes.is_empty() { return Err(RoadMapError::NoRoadsPresent)}
             for node in nodes.iter() {
                 if node.next.is_empty() { return Err(RoadMapError::DeadEndPresent) }
                 for next in node.next.iter() {
-                    if next > &nodes.len() { return Err(RoadMapError::NextIndexOutOfBounds)}
+                    if next >= &nodes.len() { return Err(RoadMapError::NextIndexOutOfBounds)}
                 }
             }
+            if lanes.len() != nodes.len() { return Err(RoadMapError::LaneCountMismatch) }
+            if lanes.iter().flat_map(|lane| lane.left.iter().chain(lane.right.iter())).any(|i| i >= &nodes.len()) {
+                return Err(RoadMapError::NextIndexOutOfBounds)
+            }
 
-            return Ok( Self { nodes });
+            if keys.values().any(|i| i >= &nodes.len()) { return Err(RoadMapError::NextIndexOutOfBounds) }
+
+            let previous: Vec<Vec<RoadIndex>> = (0..nodes.len())
+                .map(|road| (0..nodes.len()).filter(|previous| nodes[*previous].next.contains(&road)).collect())
+                .collect();
+            let conflict_zones = previous.iter().enumerate()
+                .filter(|(_, incoming)| incoming.len() > 1)
+                .map(|(road, incoming)| {
+                    let waiting_distances = incoming.iter().map(|&this| {
+                        let (this_road, length) = (&nodes[this].road, nodes[this].road.length());
+                        // the other roads are followed beyond their ends, that is where this one joins them
+                        let keeps_clear = |s: f32| incoming.iter().filter(|other| **other != this).all(|&other| {
+                            let (_, lateral) = nodes[other].road.project(this_road.point_at(s));
+                            lateral.abs() >= (lanes[this].width + lanes[other].width) / 2.0
+                        });
+                        let steps = (length / WAITING_DISTANCE_STEP) as u32;
+                        (0..=steps).map(|step| step as f32 * WAITING_DISTANCE_STEP)
+                            .find(|before_end| keeps_clear(length - before_end))
+                            .unwrap_or(length)
+                    }).collect();
+                    ConflictZone { road, incoming: incoming.clone(), waiting_distances }
+                })
+                .collect();
+            Ok( Self { nodes, lanes, keys, roundabouts: Vec::new(), previous, conflict_zones })
+        }
+        /// Roads of the roundabout should already be on the map
+        pub fn add_roundabout(&mut self, roundabout: Roundabout) {
+            self.roundabouts.push(roundabout);
+        }
+        pub fn get_roundabouts(&self) -> &[Roundabout] {
+            &self.roundabouts
+        }
+        pub fn get_conflict_zones(&self) -> &[ConflictZone] {
+            &self.conflict_zones
+        }
+        /// Conflict zone a car driving from `road` on to `next_road` goes through, if `road` merges with other roads there
+        pub fn get_conflict_zone(&self, road: RoadIndex, next_road: RoadIndex) -> Option<&ConflictZone> {
+            self.conflict_zones.iter().find(|zone| zone.road == next_road && zone.incoming.contains(&road))
         }
         pub fn get_road_by_id(&self, id: RoadIndex) -> &Road {
             &self.nodes[id].road
         }
+        pub fn get_road_index_by_key(&self, key: &str) -> Option<RoadIndex> {
+            self.keys.get(key).copied()
+        }
+        /// Lane of the road with `key`, counting from the left
+        pub fn get_lane_index_by_key(&self, key: &str, lane: usize) -> Option<RoadIndex> {
+            self.lanes.iter().position(|l| l.road == key && l.index == lane)
+        }
+        pub fn get_lane(&self, id: RoadIndex) -> &Lane {
+            &self.lanes[id]
+        }
+        pub fn get_lanes(&self) -> impl Iterator<Item=(&Road, &Lane)> + '_ {
+            self.nodes.iter().map(|n| &n.road).zip(self.lanes.iter())
+        }
+        /// Whether car can get from `from` to `to` by changing lanes once
+        pub fn are_neighbours(&self, from: RoadIndex, to: RoadIndex) -> bool {
+            let lane = &self.lanes[from];
+            lane.left == Some(to) || lane.right == Some(to)
+        }
         pub fn get_next_roads(&self, id: RoadIndex) -> &[RoadIndex] {
             &self.nodes[id].next
         }
+        /// Roads that have road `id` among their next ones
+        pub fn get_previous_roads(&self, id: RoadIndex) -> &[RoadIndex] {
+            &self.previous[id]
+        }
         pub fn get_amount_of_roads(&self) -> usize {
             self.nodes.len()
         }
         pub fn get_roads(&self) -> impl Iterator<Item=&Road> + '_ {
             self.nodes.iter().map(|n| &n.road)
         }
+
+        /// Shortest way by arc length from the start of road `from` to the start of road `to`,
+        /// both included. A* with straight line distance as a heuristic. Route may change
+        /// into a neighbouring lane, then that lane follows the one it runs alongside.
+        pub fn find_route(&self, from: RoadIndex, to: RoadIndex) -> Option<Vec<RoadIndex>> {
+            if from >= self.nodes.len() || to >= self.nodes.len() { return None; }
+
+            let target = self.nodes[to].road.get_start().coordinates;
+            let heuristic = |id: RoadIndex| {
+                let start = self.nodes[id].road.get_start().coordinates;
+                ((start.0 - target.0).powi(2) + (start.1 - target.1).powi(2)).sqrt()
+            };
+
+            let mut distances = vec![f32::INFINITY; self.nodes.len()];
+            let mut previous: Vec<Option<RoadIndex>> = vec![None; self.nodes.len()];
+            let mut queue = BinaryHeap::new();
+            distances[from] = 0.0;
+            queue.push(RouteCandidate { estimate: heuristic(from), id: from });
+
+            while let Some(RouteCandidate { id, .. }) = queue.pop() {
+                if id == to {
+                    let mut route: Vec<RoadIndex> = std::iter::successors(Some(to), |id| previous[*id]).collect();
+                    route.reverse();
+                    return Some(route);
+                }
+                let distance = distances[id] + self.nodes[id].road.length();
+                let lane_change = distances[id] + LANE_CHANGE_COST;
+                let lane = &self.lanes[id];
+                let ways = self.nodes[id].next.iter().map(|&next| (next, distance))
+                    .chain(lane.left.iter().chain(lane.right.iter()).map(|&neighbour| (neighbour, lane_change)));
+                for (next, distance) in ways {
+                    if distance < distances[next] {
+                        distances[next] = distance;
+                        previous[next] = Some(id);
+                        queue.push(RouteCandidate { estimate: distance + heuristic(next), id: next });
+                    }
+                }
+            }
+            None
+        }
+    }
+
+    /// Road waiting in A* queue, the one with the smallest estimate comes out first
+    struct RouteCandidate { estimate: f32, id: RoadIndex }
+
+    impl PartialEq for RouteCandidate {
+        fn eq(&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
+    }
+    impl Eq for RouteCandidate {}
+    impl PartialOrd for RouteCandidate {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+    }
+    impl Ord for RouteCandidate {
+        fn cmp(&self, other: &Self) -> Ordering {
+            other.estimate.total_cmp(&self.estimate).then_with(|| other.id.cmp(&self.id))
+        }
     }
 }
 
-pub use map::{RoadMap,RoadMapError,RoadNode};
-use map::*;
-use crate::model::Road;
+pub use map::{ConflictZone,Lane,Priority,RoadMap,RoadMapError,RoadNode,RoadIndex};
+use crate::algorithm::{Controller, Heuristic};
+use crate::controllers::{Lqr, Mpc, PurePursuit, Stanley};
+use crate::model::{Car, Obstacle, Position, Road, RoadTurnDirection, Roundabout, TireModel, VehicleModel, VehicleParams, DEFAULT_LANE_WIDTH};
+use crate::reservations::ReservationManager;
+use crate::signals::{Intersection, Phase, PhasePlan, DEFAULT_GREEN_TIME};
 
+#[derive(Clone)]
 pub struct Navigator {
     map: Rc<RoadMap>,
-    current_id: RoadIndex
+    current_id: RoadIndex,
+    /// roads to drive after the current one to reach the destination,
+    /// when it's empty car takes the first next road at every branch
+    route: VecDeque<RoadIndex>,
+    destination: Option<RoadIndex>,
+    /// how far along the current road the car is
+    progress: f32,
 }
 
 #[derive(Debug)]
@@ -60,23 +258,178 @@ pub enum NavigatorCreationError {
     CurrentIndexOutOfBounds
 }
 
+#[derive(Debug)]
+pub enum NavigatorRoutingError {
+    DestinationUnreachable
+}
+
 impl Navigator {
 
     pub fn new(map: Rc<RoadMap>, current_id: RoadIndex) -> Result<Self, NavigatorCreationError> {
-        if current_id > map.get_amount_of_roads() { 
+        if current_id >= map.get_amount_of_roads() {
             Err(NavigatorCreationError::CurrentIndexOutOfBounds) 
         } else {
-            Ok(Self { map, current_id })
+            Ok(Self { map, current_id, route: VecDeque::new(), destination: None, progress: 0.0 })
         }
     }
+    /// Plans the shortest route to the start of `destination` road. After car
+    /// gets there, it keeps taking the first next road at every branch.
+    /// Car already on `destination` has arrived, so there is nothing to plan.
+    pub fn set_destination(&mut self, destination: RoadIndex) -> Result<(), NavigatorRoutingError> {
+        let route = self.map.find_route(self.current_id, destination).ok_or(NavigatorRoutingError::DestinationUnreachable)?;
+        self.route = route.into_iter().skip(1).collect();
+        self.destination = (destination != self.current_id).then_some(destination);
+        Ok(())
+    }
+    pub fn get_destination(&self) -> Option<RoadIndex> {
+        self.destination
+    }
+    pub fn get_road_id(&self) -> RoadIndex {
+        self.current_id
+    }
     pub fn get_road(&self) -> &Road {
         self.map.get_road_by_id(self.current_id)
     }
     pub fn get_next_road(&self) -> &Road {
-        return self.map.get_road_by_id(self.map.get_next_roads(self.current_id)[0]);
+        self.map.get_road_by_id(self.get_upcoming_road_ids().next().expect("Upcoming roads never end"))
+    }
+    /// Indicies of roads car is going to drive after the current one, in order.
+    /// Never ends, because map is not allowed to have dead ends.
+    /// Lane changes on the route are left out, the lane car changes into runs alongside the one it leaves.
+    pub fn get_upcoming_road_ids(&self) -> impl Iterator<Item=RoadIndex> + '_ {
+        let last_planned = self.route.back().copied().unwrap_or(self.current_id);
+        let mut previous = self.current_id;
+        self.route.iter().copied()
+            .filter(move |&id| !self.map.are_neighbours(std::mem::replace(&mut previous, id), id))
+            .chain(std::iter::successors(Some(last_planned), |id| Some(self.map.get_next_roads(*id)[0])).skip(1))
+    }
+    pub fn get_upcoming_roads(&self) -> impl Iterator<Item=&Road> + '_ {
+        self.get_upcoming_road_ids().map(|id| self.map.get_road_by_id(id))
+    }
+    /// Position `distance` further along the road from the current progress,
+    /// on whichever of the upcoming roads it lands
+    pub fn get_position_ahead(&self, distance: f32) -> Position {
+        let mut remaining = self.progress + distance;
+        let mut road = self.get_road();
+        for next_road in self.get_upcoming_roads() {
+            if remaining <= road.length() { break; }
+            remaining -= road.length();
+            road = next_road;
+        }
+        road.get_position_at(remaining)
+    }
+    /// How the curvature of the road changes ahead of the current progress, up to `distance`.
+    /// Every road has a constant curvature, so each of them gives one pair of
+    /// how far ahead it starts and its curvature; the current road starts right away.
+    pub fn get_curvature_ahead(&self, distance: f32) -> impl Iterator<Item=(f32, f32)> + '_ {
+        let to_next_road = self.get_road().length() - self.progress;
+        std::iter::once((0.0, self.get_road().curvature_at(self.progress)))
+            .chain(self.get_upcoming_roads()
+                .scan(to_next_road, |start, road| {
+                    let road_start = *start;
+                    *start += road.length();
+                    Some((road_start, road.curvature_at(0.0)))
+                })
+                .take_while(move |(start, _)| *start < distance))
+    }
+    /// Looks for `point` on the route, at most `distance` ahead of the current progress
+    /// and at most `max_lateral` to either side of the road. Returns how far ahead
+    /// along the route it is, and the direction of the road there.
+    pub fn find_ahead(&self, point: (f32, f32), distance: f32, max_lateral: f32) -> Option<(f32, f32)> {
+        // how far ahead the road starts, the current one has already started
+        let mut road_start = -self.progress;
+        for road in std::iter::once(self.get_road()).chain(self.get_upcoming_roads()) {
+            if road_start > distance { break; }
+            let (s, lateral) = road.project(point);
+            let ahead = road_start + s;
+            if (0.0..=road.length()).contains(&s) && lateral.abs() <= max_lateral && ahead > 0.0 && ahead <= distance {
+                return Some((ahead, road.tangent_at(s)));
+            }
+            road_start += road.length();
+        }
+        None
+    }
+    pub fn get_progress(&self) -> f32 {
+        self.progress
+    }
+    /// Tells where along the current road the car is, without tracking how it got there
+    pub fn set_progress(&mut self, progress: f32) {
+        self.progress = progress;
     }
+
+    /// Moves progress to the point of the road closest to `point`, and switches
+    /// to the next roads as long as progress lies past the end of the current one.
+    /// Returns the progress along the road that is current afterwards.
+    pub fn track(&mut self, point: (f32, f32)) -> f32 {
+        self.progress = self.project_near_progress(point);
+        // every road could be passed at most once during a single tick
+        for _ in 0..self.map.get_amount_of_roads() {
+            let length = self.get_road().length();
+            if self.progress <= length { break; }
+            let leftover = self.progress - length;
+            self.switch_to_next_road();
+            self.progress = leftover;
+            self.progress = self.project_near_progress(point);
+        }
+        self.progress
+    }
+
+    /// Projection of `point` onto the current road. Turn repeats itself every
+    /// circumference, so of all the laps the one closest to the current progress is taken.
+    fn project_near_progress(&self, point: (f32, f32)) -> f32 {
+        let road = self.get_road();
+        let (s, _) = road.project(point);
+        match road {
+            Road::Turn { radius, .. } => {
+                let circumference = 2.0 * std::f32::consts::PI * radius;
+                [s - circumference, s, s + circumference].into_iter()
+                    .min_by(|a, b| (a - self.progress).abs().total_cmp(&(b - self.progress).abs()))
+                    .expect("There are always three laps to choose from")
+            },
+            Road::Line { .. } => s,
+        }
+    }
+
+    /// Switches to the next road, progress is reset to its start
     pub fn switch_to_next_road(&mut self) {
-        self.current_id = self.map.get_next_roads(self.current_id)[0];
+        self.progress = 0.0;
+        // lane change did not happen in time, the way to the destination is planned anew from wherever car ends up
+        let missed_lane_change = self.get_lane_change().is_some();
+        if missed_lane_change { self.route.clear(); }
+        self.current_id = self.route.pop_front().unwrap_or_else(|| self.map.get_next_roads(self.current_id)[0]);
+        if missed_lane_change { self.plan_route_again(); }
+        if self.destination == Some(self.current_id) && self.route.is_empty() {
+            self.destination = None;
+        }
+    }
+
+    /// Neighbouring lane the route changes into before the current lane is over
+    pub fn get_lane_change(&self) -> Option<RoadIndex> {
+        self.route.front().copied().filter(|next| self.map.are_neighbours(self.current_id, *next))
+    }
+
+    /// Moves into the neighbouring `lane`, keeping the progress along the road.
+    /// Unless that's the lane change route asked for, route is planned anew from there.
+    pub fn change_lane(&mut self, lane: RoadIndex) {
+        let planned = self.get_lane_change() == Some(lane);
+        self.current_id = lane;
+        if planned {
+            self.route.pop_front();
+        } else {
+            self.plan_route_again();
+        }
+    }
+
+    pub fn get_map(&self) -> &RoadMap {
+        &self.map
+    }
+
+    /// Keeps driving to the destination from the current road, or forgets it if it's out of reach now
+    fn plan_route_again(&mut self) {
+        self.route.clear();
+        if let Some(destination) = self.destination {
+            if self.set_destination(destination).is_err() { self.destination = None; }
+        }
     }
 }
 
@@ -91,8 +444,26 @@ pub enum RoadMapDeserializationError {
     InvalidFractionNotation { path: String, error: FractionNotationError },
     InvalidDirectionNotation { path: String },
     UnknownRoadReferece { reference: String },
-    RoadMapError { error: RoadMapError }
-
+    RoadMapError { error: RoadMapError },
+    /// Car is placed further along the road than the road goes
+    OffsetOutOfRoad { path: String },
+    /// There is no way from the road car starts on to its destination
+    UnreachableDestination { path: String },
+    UnknownVehicleModel { path: String, value: String },
+    UnknownTireModel { path: String, value: String },
+    UnknownVehicleType { path: String, value: String },
+    UnknownController { path: String, value: String },
+    UnknownLaneDirection { path: String, value: String },
+    /// Road has no lane with such index
+    UnknownLane { path: String },
+    UnknownPhasePlan { path: String, value: String },
+    UnknownPriority { path: String, value: String },
+    /// Road does not end facing the roundabout from outside of it, or does not start facing away from it
+    InvalidRoundaboutArm { path: String },
+    /// Line starts where it ends, or a turn, one of its lanes or a roundabout has no radius, so there is no way to drive along it
+    DegenerateRoad { path: String },
+    /// Value has to be greater than zero
+    NotPositive { path: String },
 }
 
 // TODO: Would be great to have a macro instead of a function
@@ -103,6 +474,13 @@ macro_rules! get_toml_field {
     } };
 }
 
+macro_rules! get_optional_toml_field {
+    ($table:expr, $field:expr, $as_type:expr, $error_context:expr) => { {
+        $table.get($field).map(|value| $as_type(value).ok_or_else(|| RoadMapDeserializationError::InvalidFieldType { path: format!("{}.{}",$error_context,$field) }))
+            .transpose()
+    } };
+}
+
 pub fn road_nodes_from_toml(toml_str: &str) -> Result<RoadMap, RoadMapDeserializationError>{
 
 
@@ -111,26 +489,30 @@ pub fn road_nodes_from_toml(toml_str: &str) -> Result<RoadMap, RoadMapDeserializ
 
     let roads = get_toml_field!(&table, "roads", toml::Value::as_table, "")?;
     let nodes = get_toml_field!(&table, "nodes", toml::Value::as_table, "")?;
+    let roundabouts = roundabout_layouts_from_toml(&table)?;
 
-    let mut roads_ordered: Vec<(Road, Vec<_>)> = Vec::with_capacity(roads.len());
+    let mut roads_ordered: Vec<(Road, Vec<LaneLayout>, Vec<_>)> = Vec::with_capacity(roads.len());
+    let mut priorities: Vec<Priority> = Vec::with_capacity(roads.len());
     let mut map_keys_to_indicies: HashMap<String, usize> = HashMap::with_capacity(roads.len());
 
 
     for (key, entry) in roads.iter() {
         let error_context = format!("roads.{}",key);
-        let entry = entry.as_table().ok_or_else(|| RoadMapDeserializationError::InvalidFieldType{ path: (&error_context).clone() })?;
+        let entry = entry.as_table().ok_or_else(|| RoadMapDeserializationError::InvalidFieldType{ path: error_context.clone() })?;
         let road = match get_toml_field!(entry, "type", toml::Value::as_str, &error_context)? {
             "Line" => {
                 let start_x = get_toml_field!(entry, "start_x", toml::Value::as_float, &error_context)? as f32;
                 let start_y = get_toml_field!(entry, "start_y", toml::Value::as_float, &error_context)? as f32;
                 let end_x   = get_toml_field!(entry, "end_x",   toml::Value::as_float, &error_context)? as f32;
                 let end_y   = get_toml_field!(entry, "end_y",   toml::Value::as_float, &error_context)? as f32;
+                if (start_x, start_y) == (end_x, end_y) { return Err(RoadMapDeserializationError::DegenerateRoad { path: error_context }) }
                 Ok(Road::Line { start: (start_x, start_y), end: (end_x, end_y) })
             },
             "Turn" => {
                 let x = get_toml_field!(entry, "x", toml::Value::as_float, &error_context)? as f32;
                 let y = get_toml_field!(entry, "y", toml::Value::as_float, &error_context)? as f32;
                 let r = get_toml_field!(entry, "r", toml::Value::as_float, &error_context)? as f32;
+                if r <= 0.0 { return Err(RoadMapDeserializationError::DegenerateRoad { path: format!("{}.r", &error_context) }) }
                 let start = get_toml_field!(entry, "start", toml::Value::as_str, &error_context)?;
                 let end   = get_toml_field!(entry, "end",   toml::Value::as_str, &error_context)?;
                 let dir   = get_toml_field!(entry, "dir",   toml::Value::as_str, &error_context)?;
@@ -148,31 +530,563 @@ pub fn road_nodes_from_toml(toml_str: &str) -> Result<RoadMap, RoadMapDeserializ
             unknown => Err(RoadMapDeserializationError::UnknowRoadType { path: format!("{}.type",&error_context), value: unknown.to_string() })
         }?;
 
+        let lanes = match get_optional_toml_field!(entry, "lanes", toml::Value::as_array, &error_context)? {
+            Some(lanes) if lanes.is_empty() => return Err(RoadMapDeserializationError::InvalidFieldType { path: format!("{}.lanes", &error_context) }),
+            Some(lanes) => lanes.iter().enumerate()
+                .map(|(i, lane)| lane_layout_from_toml(lane, &format!("{}.lanes[{}]", &error_context, i)))
+                .collect::<Result<Vec<LaneLayout>, RoadMapDeserializationError>>()?,
+            None => vec![LaneLayout { width: DEFAULT_LANE_WIDTH, backward: false }],
+        };
+
+        let priority = match get_optional_toml_field!(entry, "priority", toml::Value::as_str, &error_context)?.unwrap_or("Major") {
+            "Major" => Priority::Major,
+            "Minor" => Priority::Minor,
+            "Yield" => Priority::Yield,
+            "Stop" => Priority::Stop,
+            unknown => return Err(RoadMapDeserializationError::UnknownPriority { path: format!("{}.priority", &error_context), value: unknown.to_string() }),
+        };
+
         let error_context = format!("nodes.{}",key);
-        let next_roads: Vec<String> = get_toml_field!(nodes, key, toml::Value::as_array, &error_context)?
-            .iter()
-            .enumerate()
-            .map(|(i,v)| v.as_str().map(|v|v.to_string()).or_else(|| v.as_integer().map(|v|v.to_string()))
-                 .ok_or(RoadMapDeserializationError::InvalidFieldType { path: format!("{}[{}]", &error_context, i) }))
-            .collect::<Result<Vec<String>,RoadMapDeserializationError>>()?;
+        let leads_into_roundabout = roundabouts.iter().flat_map(|roundabout| roundabout.arms.iter()).any(|(from, _)| from.as_ref() == Some(key));
+        let next_roads: Vec<String> = match nodes.get(key) {
+            // roundabout adds its entry to the next roads
+            None if leads_into_roundabout => Vec::new(),
+            _ => get_toml_field!(nodes, key, toml::Value::as_array, &error_context)?
+                .iter()
+                .enumerate()
+                .map(|(i,v)| road_key_from_toml(v)
+                     .ok_or(RoadMapDeserializationError::InvalidFieldType { path: format!("{}[{}]", &error_context, i) }))
+                .collect::<Result<Vec<String>,RoadMapDeserializationError>>()?,
+        };
 
         let this_road_index = roads_ordered.len();
         map_keys_to_indicies.insert(key.to_owned(), this_road_index);
-        roads_ordered.push((road, next_roads));
+        roads_ordered.push((road, lanes, next_roads));
+        priorities.push(priority);
+    }
+
+    let mut road_keys: Vec<String> = roads.keys().cloned().collect();
+    let mut generated_roundabouts = Vec::with_capacity(roundabouts.len());
+    for layout in roundabouts {
+        let roundabout = Roundabout { coordinates: layout.coordinates, radius: layout.radius, entries: Vec::new(), ring: Vec::new() };
+        let (mut entries, mut exits) = (Vec::new(), Vec::new());
+        for (i, (from, to)) in layout.arms.iter().enumerate() {
+            let arm_road = |key: &String| map_keys_to_indicies.get(key).copied()
+                .ok_or_else(|| RoadMapDeserializationError::UnknownRoadReferece { reference: key.clone() });
+            let invalid_arm = |field: &str| RoadMapDeserializationError::InvalidRoundaboutArm { path: format!("{}.arms[{}].{}", &layout.error_context, i, field) };
+            if let Some(from) = from {
+                let from = arm_road(from)?;
+                let road = &roads_ordered[from].0;
+                let entry = roundabout.get_entry(&road.get_position_at(road.length())).ok_or_else(|| invalid_arm("from"))?;
+                let joint = roundabout.get_angle_of(&entry).ok_or_else(|| invalid_arm("from"))?;
+                let key = format!("{}.in.{}", layout.key, i);
+                roads_ordered[from].2.push(key.clone());
+                entries.push((key, entry, joint));
+            }
+            if let Some(to) = to {
+                let road = &roads_ordered[arm_road(to)?].0;
+                let exit = roundabout.get_exit(&road.get_start()).ok_or_else(|| invalid_arm("to"))?;
+                let joint = roundabout.get_angle_of(&exit).ok_or_else(|| invalid_arm("to"))?;
+                exits.push((format!("{}.out.{}", layout.key, i), exit, to.clone(), joint));
+            }
+        }
+
+        // circle is cut into turns between the points where entries and exits touch it
+        let mut joints: Vec<f32> = entries.iter().map(|(_, _, joint)| joint).chain(exits.iter().map(|(_, _, _, joint)| joint))
+            .map(|joint| joint.rem_euclid(2.0 * PI))
+            .collect();
+        joints.sort_by(f32::total_cmp);
+        joints.dedup_by(|a, b| (*a - *b).abs() < JOINT_TOLERANCE);
+        let joint_of = |angle: f32| joints.iter()
+            .position(|joint| (angle.rem_euclid(2.0 * PI) - joint).abs() < JOINT_TOLERANCE)
+            .expect("Every road touching the circle has its joint");
+        let entry_joints: Vec<usize> = entries.iter().map(|(_, _, joint)| joint_of(*joint)).collect();
+        let exit_joints: Vec<usize> = exits.iter().map(|(_, _, _, joint)| joint_of(*joint)).collect();
+        let ring_keys: Vec<String> = (0..joints.len()).map(|k| format!("{}.{}", layout.key, k)).collect();
+        let mut generated = Vec::new();
+        // cars on the circle have the right of way
+        for (k, key) in ring_keys.iter().enumerate() {
+            let end = (k + 1) % joints.len();
+            let ring = Road::Turn {
+                coordinates: roundabout.coordinates, radius: roundabout.radius,
+                start_angle: joints[k], end_angle: joints[end], direction: RoadTurnDirection::CCW,
+            };
+            // cars without a destination take the first exit
+            let next = exits.iter().zip(exit_joints.iter()).filter(|(_, joint)| **joint == end).map(|((key, ..), _)| key.clone())
+                .chain(std::iter::once(ring_keys[end].clone()))
+                .collect();
+            generated.push((key.clone(), ring, next, Priority::Major));
+        }
+        let entry_keys: Vec<String> = entries.iter().map(|(key, ..)| key.clone()).collect();
+        for ((key, entry, _), joint) in entries.into_iter().zip(entry_joints) {
+            generated.push((key, entry, vec![ring_keys[joint].clone()], Priority::Yield));
+        }
+        for (key, exit, to, _) in exits {
+            generated.push((key, exit, vec![to], Priority::Major));
+        }
+
+        for (key, road, next, priority) in generated {
+            map_keys_to_indicies.insert(key.clone(), roads_ordered.len());
+            roads_ordered.push((road, vec![LaneLayout { width: DEFAULT_LANE_WIDTH, backward: false }], next));
+            road_keys.push(key);
+            priorities.push(priority);
+        }
+        generated_roundabouts.push((roundabout, entry_keys, ring_keys));
     }
 
-    let road_nodes = roads_ordered.into_iter()
-        .map(|(road,nexts)| { 
-            Ok(RoadNode { road, next: nexts.into_iter()
-                .map(|k| map_keys_to_indicies.get(&k).ok_or_else(|| RoadMapDeserializationError::UnknownRoadReferece { reference: k.to_owned() }) )
-                .map(|r| r.map(|v| *v))
-                .collect::<Result<Vec<usize>,RoadMapDeserializationError>>()? })
+    let next_roads = roads_ordered.iter()
+        .map(|(_, _, nexts)| nexts.iter()
+            .map(|k| map_keys_to_indicies.get(k).copied().ok_or_else(|| RoadMapDeserializationError::UnknownRoadReferece { reference: k.to_owned() }))
+            .collect::<Result<Vec<usize>,RoadMapDeserializationError>>())
+        .collect::<Result<Vec<Vec<usize>>,RoadMapDeserializationError>>()?;
+    let previous_roads: Vec<Vec<usize>> = (0..roads_ordered.len())
+        .map(|road| (0..roads_ordered.len()).filter(|previous| next_roads[*previous].contains(&road)).collect())
+        .collect();
+
+    // lanes of every road get road nodes one after another, from left to right
+    let first_lanes: Vec<RoadIndex> = roads_ordered.iter()
+        .scan(0, |first, (_, lanes, _)| { let this = *first; *first += lanes.len(); Some(this) })
+        .collect();
+    // lanes going the same way, counting from the right as seen by a car driving them
+    let lanes_from_right = |road: usize, backward: bool| -> Vec<RoadIndex> {
+        let lanes = roads_ordered[road].1.iter().enumerate()
+            .filter(|(_, lane)| lane.backward == backward)
+            .map(|(i, _)| first_lanes[road] + i);
+        if backward { lanes.collect() } else { lanes.rev().collect() }
+    };
+
+    let mut road_nodes = Vec::new();
+    let mut lanes = Vec::new();
+    for (road_index, (road, layout, _)) in roads_ordered.iter().enumerate() {
+        let total_width: f32 = layout.iter().map(|lane| lane.width).sum();
+        let mut left_edge = total_width / 2.0;
+        for (i, lane) in layout.iter().enumerate() {
+            let this_lane = first_lanes[road_index] + i;
+            let lateral = left_edge - lane.width / 2.0;
+            left_edge -= lane.width;
+
+            // lane continues into the lane at the same place from the right, or the leftmost one if there are fewer of them
+            let place = lanes_from_right(road_index, lane.backward).iter().position(|l| *l == this_lane).expect("Lane is among its own lanes");
+            let connected_roads = if lane.backward { &previous_roads[road_index] } else { &next_roads[road_index] };
+            let next = connected_roads.iter()
+                .filter_map(|connected| {
+                    let continuations = lanes_from_right(*connected, lane.backward);
+                    continuations.get(place.min(continuations.len().saturating_sub(1))).copied()
+                })
+                .collect();
+
+            let same_way = |i: Option<usize>| i.and_then(|i| layout.get(i))
+                .filter(|other| other.backward == lane.backward)
+                .map(|_| first_lanes[road_index] + i.expect("Lane was found by its index"));
+            let (to_the_left, to_the_right) = if lane.backward { (i.checked_add(1), i.checked_sub(1)) } else { (i.checked_sub(1), i.checked_add(1)) };
+
+            let offset = road.offset(lateral);
+            // lane on the inner side of a tight turn would go around it the other way
+            if matches!(offset, Road::Turn { radius, .. } if radius <= 0.0) {
+                return Err(RoadMapDeserializationError::DegenerateRoad { path: format!("roads.{}.lanes[{}]", road_keys[road_index], i) });
+            }
+            road_nodes.push(RoadNode { road: if lane.backward { offset.reversed() } else { offset }, next });
+            lanes.push(Lane { road: road_keys[road_index].clone(), index: i, width: lane.width, left: same_way(to_the_left), right: same_way(to_the_right), priority: priorities[road_index] });
+        }
+    }
+
+    // key of a road leads to its rightmost lane going forward, or to the rightmost lane going backward if there are none
+    let map_keys_to_indicies = map_keys_to_indicies.into_iter()
+        .map(|(key, road)| {
+            let lane = lanes_from_right(road, false).first().copied().unwrap_or_else(|| lanes_from_right(road, true)[0]);
+            (key, lane)
         })
-        .collect::<Result<Vec<RoadNode>,RoadMapDeserializationError>>()?;
+        .collect();
+
+    let mut map = RoadMap::new_with_lanes(road_nodes, lanes, map_keys_to_indicies).map_err(|error| RoadMapDeserializationError::RoadMapError { error })?;
+    for (mut roundabout, entries, ring) in generated_roundabouts {
+        let index_of = |key: &String| map.get_road_index_by_key(key).expect("Road was generated for the roundabout");
+        roundabout.entries = entries.iter().map(index_of).collect();
+        roundabout.ring = ring.iter().map(index_of).collect();
+        map.add_roundabout(roundabout);
+    }
+    Ok(map)
+}
+
+/// Roundabouts get two roads closer than that to each other (in radians around the circle) joined at the same point
+const JOINT_TOLERANCE: f32 = 1e-3;
+
+/// Roundabout as the map file describes it, before its roads are made
+struct RoundaboutLayout {
+    key: String,
+    coordinates: (f32, f32),
+    radius: f32,
+    /// keys of the roads leading into the roundabout and out of it
+    arms: Vec<(Option<String>, Option<String>)>,
+    error_context: String,
+}
+
+fn roundabout_layouts_from_toml(table: &toml::Table) -> Result<Vec<RoundaboutLayout>, RoadMapDeserializationError> {
+    let Some(roundabouts) = get_optional_toml_field!(table, "roundabouts", toml::Value::as_table, "")? else {
+        return Ok(Vec::new());
+    };
+
+    roundabouts.iter().map(|(key, entry)| {
+        let error_context = format!("roundabouts.{}",key);
+        let entry = entry.as_table().ok_or_else(|| RoadMapDeserializationError::InvalidFieldType{ path: error_context.clone() })?;
+        let x = get_toml_field!(entry, "x", toml::Value::as_float, &error_context)? as f32;
+        let y = get_toml_field!(entry, "y", toml::Value::as_float, &error_context)? as f32;
+        let r = get_toml_field!(entry, "r", toml::Value::as_float, &error_context)? as f32;
+        if r <= 0.0 { return Err(RoadMapDeserializationError::DegenerateRoad { path: format!("{}.r", &error_context) }) }
+
+        let arms = get_toml_field!(entry, "arms", toml::Value::as_array, &error_context)?.iter().enumerate().map(|(i, arm)| {
+            let error_context = format!("{}.arms[{}]", &error_context, i);
+            let arm = arm.as_table().ok_or_else(|| RoadMapDeserializationError::InvalidFieldType{ path: error_context.clone() })?;
+            let road_key = |field: &str| get_optional_toml_field!(arm, field, Some, &error_context)?
+                .map(|value| road_key_from_toml(value).ok_or_else(|| RoadMapDeserializationError::InvalidFieldType { path: format!("{}.{}", &error_context, field) }))
+                .transpose();
+            Ok((road_key("from")?, road_key("to")?))
+        }).collect::<Result<Vec<_>, RoadMapDeserializationError>>()?;
+
+        Ok(RoundaboutLayout { key: key.clone(), coordinates: (x, y), radius: r, arms, error_context })
+    }).collect()
+}
+
+/// How wide a lane is and which way it goes, as the map file describes it
+struct LaneLayout { width: f32, backward: bool }
+
+fn lane_layout_from_toml(value: &toml::Value, error_context: &str) -> Result<LaneLayout, RoadMapDeserializationError> {
+    let entry = value.as_table().ok_or_else(|| RoadMapDeserializationError::InvalidFieldType { path: error_context.to_owned() })?;
+    let width = get_optional_toml_field!(entry, "width", toml::Value::as_float, error_context)?.map_or(DEFAULT_LANE_WIDTH, |w| w as f32);
+    let backward = match get_optional_toml_field!(entry, "direction", toml::Value::as_str, error_context)?.unwrap_or("Forward") {
+        "Forward" => false,
+        "Backward" => true,
+        unknown => return Err(RoadMapDeserializationError::UnknownLaneDirection { path: format!("{}.direction", error_context), value: unknown.to_string() }),
+    };
+    Ok(LaneLayout { width, backward })
+}
+
+/// Reads optional `[cars]` section, placing cars on the roads of the `map`
+/// that was read from the same file.
+pub fn cars_from_toml(toml_str: &str, map: &Rc<RoadMap>) -> Result<Vec<Car>, RoadMapDeserializationError> {
+
+    let table: toml::Table = toml::from_str(toml_str)
+        .map_err(|error| RoadMapDeserializationError::InvalidFormat { error })?;
+
+    let Some(cars) = get_optional_toml_field!(&table, "cars", toml::Value::as_table, "")? else {
+        return Ok(Vec::new());
+    };
+
+    cars.iter().map(|(key, entry)| {
+        let error_context = format!("cars.{}",key);
+        let entry = entry.as_table().ok_or_else(|| RoadMapDeserializationError::InvalidFieldType{ path: error_context.clone() })?;
+
+        let road_value = get_toml_field!(entry, "road", Some, &error_context)?;
+        let road_key = road_key_from_toml(road_value)
+            .ok_or_else(|| RoadMapDeserializationError::InvalidFieldType { path: format!("{}.road", &error_context) })?;
+        let road_index = map.get_road_index_by_key(&road_key)
+            .ok_or_else(|| RoadMapDeserializationError::UnknownRoadReferece { reference: road_key.clone() })?;
+        let road_index = match get_optional_toml_field!(entry, "lane", toml::Value::as_integer, &error_context)? {
+            Some(lane) => usize::try_from(lane).ok()
+                .and_then(|lane| map.get_lane_index_by_key(&road_key, lane))
+                .ok_or_else(|| RoadMapDeserializationError::UnknownLane { path: format!("{}.lane", &error_context) })?,
+            None => road_index,
+        };
+
+        let offset  = get_optional_toml_field!(entry, "offset",  toml::Value::as_float, &error_context)?.unwrap_or(0.0) as f32;
+        let lateral = get_optional_toml_field!(entry, "lateral", toml::Value::as_float, &error_context)?.unwrap_or(0.0) as f32;
+        let speed   = get_optional_toml_field!(entry, "speed",   toml::Value::as_float, &error_context)?.unwrap_or(0.0) as f32;
+        let cruise_speed = get_optional_toml_field!(entry, "cruise_speed", toml::Value::as_float, &error_context)?.map(|v| v as f32);
+        let orientation = get_optional_toml_field!(entry, "orientation", toml::Value::as_str, &error_context)?
+            .map(|o| fraction_notation_to_angle(o)
+                 .map_err(|error| RoadMapDeserializationError::InvalidFractionNotation { path: format!("{}.orientation",&error_context), error }))
+            .transpose()?;
+
+        let road = map.get_road_by_id(road_index);
+        if !(0.0..=road.length()).contains(&offset) {
+            return Err(RoadMapDeserializationError::OffsetOutOfRoad { path: format!("{}.offset", &error_context) });
+        }
+
+        let mut navigator = Navigator::new(Rc::clone(map), road_index).expect("Index was taken from the map itself");
+        navigator.set_progress(offset);
+        if let Some(destination_value) = get_optional_toml_field!(entry, "destination", Some, &error_context)? {
+            let destination_key = road_key_from_toml(destination_value)
+                .ok_or_else(|| RoadMapDeserializationError::InvalidFieldType { path: format!("{}.destination", &error_context) })?;
+            let destination = map.get_road_index_by_key(&destination_key)
+                .ok_or_else(|| RoadMapDeserializationError::UnknownRoadReferece { reference: destination_key.clone() })?;
+            navigator.set_destination(destination)
+                .map_err(|_| RoadMapDeserializationError::UnreachableDestination { path: format!("{}.destination", &error_context) })?;
+        }
+        let mut car = Car::from_navigator(navigator);
+        let on_road = road.get_position_at(offset);
+        // positive lateral offset is to the left of the road
+        car.position.coordinates = (
+            on_road.coordinates.0 - lateral * on_road.orientation.sin(),
+            on_road.coordinates.1 + lateral * on_road.orientation.cos()
+        );
+        car.position.orientation = orientation.unwrap_or(on_road.orientation);
+        car.previous_position = car.position.clone();
+        car.speed = speed;
+        if let Some(cruise_speed) = cruise_speed { car.cruise_speed = cruise_speed; }
+        car.params = vehicle_params_from_toml(entry, &error_context)?;
+        if let Some(model) = get_optional_toml_field!(entry, "model", toml::Value::as_table, &error_context)? {
+            car.vehicle_model = vehicle_model_from_toml(model, &format!("{}.model", &error_context))?;
+        }
+        if let Some(controller) = get_optional_toml_field!(entry, "controller", toml::Value::as_table, &error_context)? {
+            car.controller = controller_from_toml(controller, &format!("{}.controller", &error_context))?;
+        }
+        Ok(car)
+    }).collect()
+}
+
+/// Reads the optional `[obstacles]` section, obstacles are 20 by 20 pixels unless told otherwise
+pub fn obstacles_from_toml(toml_str: &str) -> Result<Vec<Obstacle>, RoadMapDeserializationError> {
+
+    let table: toml::Table = toml::from_str(toml_str)
+        .map_err(|error| RoadMapDeserializationError::InvalidFormat { error })?;
+
+    let Some(obstacles) = get_optional_toml_field!(&table, "obstacles", toml::Value::as_table, "")? else {
+        return Ok(Vec::new());
+    };
+
+    obstacles.iter().map(|(key, entry)| {
+        let error_context = format!("obstacles.{}",key);
+        let entry = entry.as_table().ok_or_else(|| RoadMapDeserializationError::InvalidFieldType{ path: error_context.clone() })?;
 
-    RoadMap::new(road_nodes).map_err(|error| RoadMapDeserializationError::RoadMapError { error })
+        let x      = get_toml_field!(entry, "x", toml::Value::as_float, &error_context)? as f32;
+        let y      = get_toml_field!(entry, "y", toml::Value::as_float, &error_context)? as f32;
+        let length = get_optional_toml_field!(entry, "length", toml::Value::as_float, &error_context)?.unwrap_or(20.0) as f32;
+        let width  = get_optional_toml_field!(entry, "width",  toml::Value::as_float, &error_context)?.unwrap_or(20.0) as f32;
+        let orientation = get_optional_toml_field!(entry, "orientation", toml::Value::as_str, &error_context)?
+            .map(|o| fraction_notation_to_angle(o)
+                 .map_err(|error| RoadMapDeserializationError::InvalidFractionNotation { path: format!("{}.orientation",&error_context), error }))
+            .transpose()?
+            .unwrap_or(0.0);
+
+        Ok(Obstacle { position: Position { coordinates: (x, y), orientation }, length, width })
+    }).collect()
+}
+
+/// Reads the optional `[intersections]` section. Every phase lists the roads that get green light together,
+/// either by key, which means all lanes going the same way as the road, or as `{ road, lane }` tables.
+pub fn intersections_from_toml(toml_str: &str, map: &RoadMap) -> Result<Vec<Intersection>, RoadMapDeserializationError> {
+
+    let table: toml::Table = toml::from_str(toml_str)
+        .map_err(|error| RoadMapDeserializationError::InvalidFormat { error })?;
+
+    let Some(intersections) = get_optional_toml_field!(&table, "intersections", toml::Value::as_table, "")? else {
+        return Ok(Vec::new());
+    };
+
+    intersections.iter().map(|(key, entry)| {
+        let error_context = format!("intersections.{}",key);
+        let entry = entry.as_table().ok_or_else(|| RoadMapDeserializationError::InvalidFieldType{ path: error_context.clone() })?;
+        let float = |field: &str| -> Result<Option<f32>, RoadMapDeserializationError> {
+            Ok(get_optional_toml_field!(entry, field, toml::Value::as_float, &error_context)?.map(|v| v as f32))
+        };
+
+        let phases = get_toml_field!(entry, "phases", toml::Value::as_array, &error_context)?;
+        // lights need something to show
+        if phases.is_empty() { return Err(RoadMapDeserializationError::InvalidFieldType { path: format!("{}.phases", &error_context) }) }
+        let phases = phases.iter().enumerate().map(|(i, phase)| {
+            let error_context = format!("{}.phases[{}]", &error_context, i);
+            let phase = phase.as_table().ok_or_else(|| RoadMapDeserializationError::InvalidFieldType{ path: error_context.clone() })?;
+            let green = get_optional_toml_field!(phase, "green", toml::Value::as_float, &error_context)?.map_or(DEFAULT_GREEN_TIME, |v| v as f32);
+            let mut roads = Vec::new();
+            let phase_roads = get_toml_field!(phase, "roads", toml::Value::as_array, &error_context)?;
+            if phase_roads.is_empty() { return Err(RoadMapDeserializationError::InvalidFieldType { path: format!("{}.roads", &error_context) }) }
+            for (j, road) in phase_roads.iter().enumerate() {
+                roads.extend(signalled_lanes_from_toml(road, map, &format!("{}.roads[{}]", &error_context, j))?);
+            }
+            Ok(Phase { roads, green })
+        }).collect::<Result<Vec<_>, _>>()?;
+
+        let plan = match get_optional_toml_field!(entry, "plan", toml::Value::as_str, &error_context)?.unwrap_or("FixedTime") {
+            "FixedTime" => PhasePlan::FixedTime,
+            "Actuated" => PhasePlan::Actuated {
+                min_green: float("min_green")?.unwrap_or(5.0),
+                max_green: float("max_green")?.unwrap_or(20.0),
+                detection_distance: float("detection_distance")?.unwrap_or(100.0),
+            },
+            unknown => return Err(RoadMapDeserializationError::UnknownPhasePlan { path: format!("{}.plan", &error_context), value: unknown.to_string() }),
+        };
+
+        let mut intersection = Intersection::new(phases, plan);
+        if let Some(amber) = float("amber")? { intersection.amber = amber; }
+        if let Some(all_red) = float("all_red")? { intersection.all_red = all_red; }
+        Ok(intersection)
+    }).collect()
 }
 
+/// Reads the optional `[reservations]` section, roads inside every intersection are listed like the roads of a phase
+pub fn reservation_managers_from_toml(toml_str: &str, map: &RoadMap) -> Result<Vec<ReservationManager>, RoadMapDeserializationError> {
+
+    let table: toml::Table = toml::from_str(toml_str)
+        .map_err(|error| RoadMapDeserializationError::InvalidFormat { error })?;
+
+    let Some(managers) = get_optional_toml_field!(&table, "reservations", toml::Value::as_table, "")? else {
+        return Ok(Vec::new());
+    };
+
+    managers.iter().map(|(key, entry)| {
+        let error_context = format!("reservations.{}",key);
+        let entry = entry.as_table().ok_or_else(|| RoadMapDeserializationError::InvalidFieldType{ path: error_context.clone() })?;
+        let mut roads = Vec::new();
+        for (i, road) in get_toml_field!(entry, "roads", toml::Value::as_array, &error_context)?.iter().enumerate() {
+            roads.extend(signalled_lanes_from_toml(road, map, &format!("{}.roads[{}]", &error_context, i))?);
+        }
+
+        let mut manager = ReservationManager::new(roads, map);
+        if let Some(distance) = get_optional_toml_field!(entry, "request_distance", toml::Value::as_float, &error_context)? {
+            manager.request_distance = distance as f32;
+        }
+        Ok(manager)
+    }).collect()
+}
+
+/// Lanes a light of a phase is shown to, or a reservation manager looks after: a single `{ road, lane }`,
+/// or every lane going the same way as the road `key`
+fn signalled_lanes_from_toml(value: &toml::Value, map: &RoadMap, error_context: &str) -> Result<Vec<RoadIndex>, RoadMapDeserializationError> {
+    if let Some(entry) = value.as_table() {
+        let road_key = road_key_from_toml(get_toml_field!(entry, "road", Some, error_context)?)
+            .ok_or_else(|| RoadMapDeserializationError::InvalidFieldType { path: format!("{}.road", error_context) })?;
+        let lane = get_toml_field!(entry, "lane", toml::Value::as_integer, error_context)?;
+        let index = usize::try_from(lane).ok()
+            .and_then(|lane| map.get_lane_index_by_key(&road_key, lane))
+            .ok_or_else(|| RoadMapDeserializationError::UnknownLane { path: format!("{}.lane", error_context) })?;
+        return Ok(vec![index]);
+    }
+    let road_key = road_key_from_toml(value)
+        .ok_or_else(|| RoadMapDeserializationError::InvalidFieldType { path: error_context.to_string() })?;
+    let rightmost = map.get_road_index_by_key(&road_key)
+        .ok_or_else(|| RoadMapDeserializationError::UnknownRoadReferece { reference: road_key.clone() })?;
+    Ok(std::iter::successors(Some(rightmost), |lane| map.get_lane(*lane).left).collect())
+}
+
+/// Preset chosen by `vehicle`, with any of its values overriden by the `params` table
+fn vehicle_params_from_toml(entry: &toml::Table, error_context: &str) -> Result<VehicleParams, RoadMapDeserializationError> {
+    let mut params = match get_optional_toml_field!(entry, "vehicle", toml::Value::as_str, error_context)?.unwrap_or("Car") {
+        "Car" => VehicleParams::car(),
+        "SportsCar" => VehicleParams::sports_car(),
+        "Truck" => VehicleParams::truck(),
+        "Bus" => VehicleParams::bus(),
+        unknown => return Err(RoadMapDeserializationError::UnknownVehicleType { path: format!("{}.vehicle", error_context), value: unknown.to_string() }),
+    };
+
+    let Some(overrides) = get_optional_toml_field!(entry, "params", toml::Value::as_table, error_context)? else {
+        return Ok(params);
+    };
+    let error_context = format!("{}.params", error_context);
+    for (field, value) in [
+        ("length", &mut params.length),
+        ("width", &mut params.width),
+        ("brakes", &mut params.brakes),
+        ("acceleration", &mut params.acceleration),
+        ("steer_speed", &mut params.steer_speed),
+        ("max_steer", &mut params.max_steer),
+        ("max_speed", &mut params.max_speed),
+        ("max_reverse_speed", &mut params.max_reverse_speed),
+        ("steering_wheel_speed", &mut params.steering_wheel_speed),
+        ("max_steering_wheel_angle", &mut params.max_steering_wheel_angle),
+    ] {
+        if let Some(v) = get_optional_toml_field!(overrides, field, toml::Value::as_float, &error_context)? {
+            *value = v as f32;
+        }
+    }
+    Ok(params)
+}
+
+fn controller_from_toml(entry: &toml::Table, error_context: &str) -> Result<Box<dyn Controller>, RoadMapDeserializationError> {
+    let float_or = |field: &str, default: f32| -> Result<f32, RoadMapDeserializationError> {
+        Ok(get_optional_toml_field!(entry, field, toml::Value::as_float, error_context)?.map_or(default, |v| v as f32))
+    };
+    match get_toml_field!(entry, "type", toml::Value::as_str, error_context)? {
+        "Heuristic" => {
+            let default = Heuristic::default();
+            Ok(Box::new(Heuristic { lookahead: float_or("lookahead", default.lookahead)? }))
+        },
+        "PurePursuit" => {
+            let default = PurePursuit::default();
+            Ok(Box::new(PurePursuit {
+                lookahead_time: float_or("lookahead_time", default.lookahead_time)?,
+                min_lookahead:  float_or("min_lookahead",  default.min_lookahead)?,
+            }))
+        },
+        "Stanley" => {
+            let default = Stanley::default();
+            Ok(Box::new(Stanley {
+                gain:      float_or("gain",      default.gain)?,
+                softening: float_or("softening", default.softening)?,
+            }))
+        },
+        "Lqr" => {
+            let default = Lqr::default();
+            Ok(Box::new(Lqr {
+                lateral_weight:  float_or("lateral_weight",  default.lateral_weight)?,
+                heading_weight:  float_or("heading_weight",  default.heading_weight)?,
+                steering_weight: float_or("steering_weight", default.steering_weight)?,
+                lookahead:       float_or("lookahead",       default.lookahead)?,
+            }))
+        },
+        "Mpc" => {
+            let default = Mpc::default();
+            let steering_offsets = match get_optional_toml_field!(entry, "steering_offsets", toml::Value::as_array, error_context)? {
+                Some(offsets) if offsets.is_empty() => return Err(RoadMapDeserializationError::InvalidFieldType { path: format!("{}.steering_offsets", error_context) }),
+                Some(offsets) => offsets.iter().enumerate()
+                    .map(|(i, offset)| offset.as_float().map(|offset| offset as f32)
+                        .ok_or_else(|| RoadMapDeserializationError::InvalidFieldType { path: format!("{}.steering_offsets[{}]", error_context, i) }))
+                    .collect::<Result<Vec<f32>, RoadMapDeserializationError>>()?,
+                None => default.steering_offsets,
+            };
+            // prediction needs some time to look into, cut in steps of some length
+            let positive_or = |field: &str, default: f32| -> Result<f32, RoadMapDeserializationError> {
+                Some(float_or(field, default)?).filter(|value| *value > 0.0)
+                    .ok_or_else(|| RoadMapDeserializationError::NotPositive { path: format!("{}.{}", error_context, field) })
+            };
+            Ok(Box::new(Mpc {
+                horizon:          positive_or("horizon",          default.horizon)?,
+                steps_per_second: positive_or("steps_per_second", default.steps_per_second)?,
+                lateral_weight:   float_or("lateral_weight",   default.lateral_weight)?,
+                heading_weight:   float_or("heading_weight",   default.heading_weight)?,
+                speed_weight:     float_or("speed_weight",     default.speed_weight)?,
+                lookahead:        float_or("lookahead",        default.lookahead)?,
+                steering_offsets,
+            }))
+        },
+        unknown => Err(RoadMapDeserializationError::UnknownController { path: format!("{}.type", error_context), value: unknown.to_string() })
+    }
+}
+
+/// Slip stiffness of tires, when map file does not tell otherwise
+const DEFAULT_TIRE_STIFFNESS: f64 = 8.0;
+
+fn vehicle_model_from_toml(entry: &toml::Table, error_context: &str) -> Result<VehicleModel, RoadMapDeserializationError> {
+    match get_toml_field!(entry, "type", toml::Value::as_str, error_context)? {
+        "Point" => Ok(VehicleModel::Point),
+        "Bicycle" => {
+            let wheelbase      = get_toml_field!(entry, "wheelbase",      toml::Value::as_float, error_context)? as f32;
+            let steering_ratio = get_toml_field!(entry, "steering_ratio", toml::Value::as_float, error_context)? as f32;
+            Ok(VehicleModel::Bicycle { wheelbase, steering_ratio })
+        },
+        "Dynamic" => {
+            let wheelbase       = get_toml_field!(entry, "wheelbase",      toml::Value::as_float, error_context)? as f32;
+            let steering_ratio  = get_toml_field!(entry, "steering_ratio", toml::Value::as_float, error_context)? as f32;
+            let friction        = get_toml_field!(entry, "friction",       toml::Value::as_float, error_context)? as f32;
+            let front_stiffness = get_optional_toml_field!(entry, "front_stiffness", toml::Value::as_float, error_context)?.unwrap_or(DEFAULT_TIRE_STIFFNESS) as f32;
+            let rear_stiffness  = get_optional_toml_field!(entry, "rear_stiffness",  toml::Value::as_float, error_context)?.unwrap_or(DEFAULT_TIRE_STIFFNESS) as f32;
+            let tire = match get_optional_toml_field!(entry, "tire", toml::Value::as_str, error_context)?.unwrap_or("Pacejka") {
+                "Linear" => TireModel::Linear,
+                "Pacejka" => TireModel::Pacejka {
+                    shape:     get_optional_toml_field!(entry, "tire_shape",     toml::Value::as_float, error_context)?.unwrap_or(1.9) as f32,
+                    curvature: get_optional_toml_field!(entry, "tire_curvature", toml::Value::as_float, error_context)?.unwrap_or(0.97) as f32,
+                },
+                unknown => return Err(RoadMapDeserializationError::UnknownTireModel { path: format!("{}.tire", error_context), value: unknown.to_string() }),
+            };
+            Ok(VehicleModel::Dynamic { wheelbase, steering_ratio, friction, front_stiffness, rear_stiffness, tire })
+        },
+        unknown => Err(RoadMapDeserializationError::UnknownVehicleModel { path: format!("{}.type", error_context), value: unknown.to_string() })
+    }
+}
+
+/// Roads may be referenced either by a string or by an integer key
+fn road_key_from_toml(value: &toml::Value) -> Option<String> {
+    value.as_str().map(|v| v.to_string()).or_else(|| value.as_integer().map(|v| v.to_string()))
+}
 
 #[derive(Debug)]
 pub enum FractionNotationError {
@@ -192,13 +1106,16 @@ fn fraction_notation_to_angle(s: &str) -> Result<f32, FractionNotationError> {
 use std::fmt::Debug;
 impl Debug for Navigator {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Navigator").field("map", &"hidden").field("current_id", &self.current_id).finish()
+        f.debug_struct("Navigator").field("map", &"hidden").field("current_id", &self.current_id)
+            .field("route", &self.route).field("destination", &self.destination).field("progress", &self.progress).finish()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::signals::DEFAULT_ALL_RED_TIME;
+    use crate::util::normalize_angle;
 
     #[test]
     fn can_follow_circular_road() {
@@ -228,4 +1145,401 @@ mod tests {
         assert_eq!(navigator.current_id, 0);
     }
 
+    const SQUARE_MAP: &str = r#"
+        [roads]
+        a = { type = 'Line', start_x = 0.0, start_y = 0.0, end_x = 100.0, end_y = 0.0 }
+        b = { type = 'Line', start_x = 100.0, start_y = 0.0, end_x = 0.0, end_y = 0.0 }
+        [nodes]
+        a = ['b']
+        b = ['a']
+    "#;
+
+    #[test]
+    fn places_cars_from_map_file() {
+        let toml = format!("{SQUARE_MAP}{}", r#"
+            [cars]
+            first = { road = 'b', offset = 25.0, lateral = 5.0, speed = 30.0, cruise_speed = 100.0, vehicle = 'Truck', params = { max_speed = 90.0 } }
+            second = { road = 'a', orientation = '1/2', model = { type = 'Bicycle', wheelbase = 24.0, steering_ratio = 15.0 }, controller = { type = 'Stanley', gain = 3.0 } }
+            third = { road = 'a', controller = { type = 'Mpc', horizon = 0.5, steering_offsets = [-1.0, 0.0, 1.0] } }
+        "#);
+        let map = Rc::new(road_nodes_from_toml(&toml).expect("Should have read the map"));
+        let cars = cars_from_toml(&toml, &map).expect("Should have read the cars");
+
+        assert_eq!(cars.len(), 3);
+        let first = &cars[0];
+        assert_eq!(first.navigator.current_id, map.get_road_index_by_key("b").unwrap());
+        assert!((first.position.coordinates.0 - 75.0).abs() < 1e-3);
+        assert!((first.position.coordinates.1 + 5.0).abs() < 1e-3);
+        assert_eq!(first.speed, 30.0);
+        assert_eq!(first.cruise_speed, 100.0);
+        assert_eq!(first.params.length, VehicleParams::truck().length);
+        assert_eq!(first.params.max_speed, 90.0);
+        let second = &cars[1];
+        assert_eq!(second.position.coordinates, (0.0, 0.0));
+        assert!((second.position.orientation - std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+        assert!(matches!(second.vehicle_model, VehicleModel::Bicycle { wheelbase, steering_ratio } if wheelbase == 24.0 && steering_ratio == 15.0));
+        assert_eq!(format!("{:?}", second.controller), "Stanley { gain: 3.0, softening: 10.0 }");
+        assert!(format!("{:?}", cars[2].controller).ends_with("steering_offsets: [-1.0, 0.0, 1.0] }"));
+    }
+
+    #[test]
+    fn reports_path_of_invalid_car() {
+        let toml = format!("{SQUARE_MAP}{}", "[cars]\nfirst = { road = 'a', offset = 120.0 }\n");
+        let map = Rc::new(road_nodes_from_toml(&toml).expect("Should have read the map"));
+        match cars_from_toml(&toml, &map) {
+            Err(RoadMapDeserializationError::OffsetOutOfRoad { path }) => assert_eq!(path, "cars.first.offset"),
+            other => panic!("unexpected result: {other:?}"),
+        }
+        let toml = format!("{SQUARE_MAP}{}", "[cars]\nfirst = { road = 'c' }\n");
+        assert!(matches!(cars_from_toml(&toml, &map), Err(RoadMapDeserializationError::UnknownRoadReferece { .. })));
+        let toml = format!("{SQUARE_MAP}{}", "[cars]\nfirst = { road = 'a', controller = { type = 'Joystick' } }\n");
+        match cars_from_toml(&toml, &map) {
+            Err(RoadMapDeserializationError::UnknownController { path, value }) => assert_eq!((path.as_str(), value.as_str()), ("cars.first.controller.type", "Joystick")),
+            other => panic!("unexpected result: {other:?}"),
+        }
+        let toml = format!("{SQUARE_MAP}{}", "[cars]\nfirst = { road = 'a', controller = { type = 'Mpc', steering_offsets = [0.0, 'left'] } }\n");
+        match cars_from_toml(&toml, &map) {
+            Err(RoadMapDeserializationError::InvalidFieldType { path }) => assert_eq!(path, "cars.first.controller.steering_offsets[1]"),
+            other => panic!("unexpected result: {other:?}"),
+        }
+        for (field, value) in [("horizon", "0.0"), ("steps_per_second", "-5.0")] {
+            let toml = format!("{SQUARE_MAP}[cars]\nfirst = {{ road = 'a', controller = {{ type = 'Mpc', {field} = {value} }} }}\n");
+            match cars_from_toml(&toml, &map) {
+                Err(RoadMapDeserializationError::NotPositive { path }) => assert_eq!(path, format!("cars.first.controller.{field}")),
+                other => panic!("unexpected result: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn curvature_ahead_spans_several_roads() {
+        let map = Rc::new(branching_map());
+        let mut navigator = Navigator::new(Rc::clone(&map), 0).expect("Should have created the navigator");
+        navigator.set_progress(40.);
+        let starts = |navigator: &Navigator| navigator.get_curvature_ahead(200.).map(|(start, _)| start).collect::<Vec<f32>>();
+        // long road takes the whole distance
+        assert_eq!(starts(&navigator), [0., 60.]);
+        // route decides which roads are ahead
+        navigator.set_destination(3).expect("Should have found a route");
+        assert_eq!(starts(&navigator), [0., 60., 160.]);
+    }
+
+    #[test]
+    fn finds_points_ahead_on_route() {
+        let map = Rc::new(branching_map());
+        let mut navigator = Navigator::new(Rc::clone(&map), 0).expect("Should have created the navigator");
+        navigator.set_progress(40.);
+        navigator.set_destination(3).expect("Should have found a route");
+
+        assert_eq!(navigator.find_ahead((150., 2.), 200., 10.), Some((110., 0.)));
+        // behind, too far to the side, or further than asked
+        assert_eq!(navigator.find_ahead((20., 0.), 200., 10.), None);
+        assert_eq!(navigator.find_ahead((150., 20.), 200., 10.), None);
+        assert_eq!(navigator.find_ahead((150., 0.), 100., 10.), None);
+    }
+
+    /// Two ways from `start` to `finish`: a short one through `short` and a long one through `long`
+    fn branching_map() -> RoadMap {
+        let line = |start: (f32, f32), end: (f32, f32)| Road::Line { start, end };
+        RoadMap::new(vec![
+            /* 0 start  */ RoadNode { road: line((0., 0.), (100., 0.)), next: vec![2, 1] },
+            /* 1 short  */ RoadNode { road: line((100., 0.), (200., 0.)), next: vec![3] },
+            /* 2 long   */ RoadNode { road: line((100., 0.), (200., 300.)), next: vec![4] },
+            /* 3 finish */ RoadNode { road: line((200., 0.), (0., 0.)), next: vec![0] },
+            /* 4 detour */ RoadNode { road: line((200., 300.), (200., 0.)), next: vec![3] },
+        ]).expect("Should have created RoadMap")
+    }
+
+    #[test]
+    fn finds_shortest_route() {
+        let map = branching_map();
+        assert_eq!(map.find_route(0, 3), Some(vec![0, 1, 3]));
+        assert_eq!(map.find_route(0, 4), Some(vec![0, 2, 4]));
+        assert_eq!(map.find_route(0, 0), Some(vec![0]));
+        assert_eq!(map.find_route(0, 5), None);
+    }
+
+    #[test]
+    fn rejects_indices_past_last_road() {
+        let line = || Road::Line { start: (0., 0.), end: (100., 0.) };
+        assert!(matches!(RoadMap::new(vec![RoadNode { road: line(), next: vec![1] }]), Err(RoadMapError::NextIndexOutOfBounds)));
+        let map = Rc::new(RoadMap::new(vec![RoadNode { road: line(), next: vec![0] }]).expect("Should have created RoadMap"));
+        assert!(matches!(Navigator::new(Rc::clone(&map), 1), Err(NavigatorCreationError::CurrentIndexOutOfBounds)));
+        assert!(Navigator::new(map, 0).is_ok());
+    }
+
+    #[test]
+    fn follows_route_at_branches() {
+        let mut navigator = Navigator::new(Rc::new(branching_map()), 0).expect("Should have created the navigator");
+        // without destination, first next road is taken
+        assert_eq!(navigator.get_upcoming_road_ids().take(3).collect::<Vec<_>>(), vec![2, 4, 3]);
+
+        navigator.set_destination(3).expect("Destination should be reachable");
+        assert_eq!(navigator.get_upcoming_road_ids().take(3).collect::<Vec<_>>(), vec![1, 3, 0]);
+        navigator.switch_to_next_road();
+        assert_eq!(navigator.current_id, 1);
+        navigator.switch_to_next_road();
+        assert_eq!(navigator.current_id, 3);
+        assert_eq!(navigator.get_destination(), None);
+        navigator.switch_to_next_road();
+        navigator.switch_to_next_road();
+        assert_eq!(navigator.current_id, 2);
+
+        // car is already there
+        navigator.set_destination(2).expect("Destination should be reachable");
+        assert_eq!(navigator.get_destination(), None);
+        assert_eq!(navigator.get_upcoming_road_ids().take(2).collect::<Vec<_>>(), vec![4, 3]);
+    }
+
+    /// Square of two-way roads, with an extra lane going forward on `a`
+    const LANES_MAP: &str = r#"
+        [roads]
+        a = { type = 'Line', start_x = 0.0, start_y = 0.0, end_x = 200.0, end_y = 0.0, lanes = [{ direction = 'Backward' }, {}, { width = 30.0 }] }
+        b = { type = 'Line', start_x = 200.0, start_y = 0.0, end_x = 200.0, end_y = 200.0, lanes = [{ direction = 'Backward' }, {}] }
+        c = { type = 'Line', start_x = 200.0, start_y = 200.0, end_x = 0.0, end_y = 200.0, lanes = [{ direction = 'Backward' }, {}] }
+        d = { type = 'Line', start_x = 0.0, start_y = 200.0, end_x = 0.0, end_y = 0.0, lanes = [{ direction = 'Backward' }, {}] }
+        [nodes]
+        a = ['b']
+        b = ['c']
+        c = ['d']
+        d = ['a']
+    "#;
+
+    #[test]
+    fn builds_lane_graph() {
+        let map = Rc::new(road_nodes_from_toml(LANES_MAP).expect("Should have read the map"));
+        assert_eq!(map.get_amount_of_roads(), 9);
+        let lane = |key: &str, lane: usize| map.get_lane_index_by_key(key, lane).expect("Lane should exist");
+
+        // lanes are 110 pixels wide altogether, centered on the road
+        assert_eq!(map.get_road_by_id(lane("a", 0)).get_start().coordinates, (200., 35.));
+        assert_eq!(map.get_road_by_id(lane("a", 1)).get_start().coordinates, (0., -5.));
+        assert_eq!(map.get_road_by_id(lane("a", 2)).get_start().coordinates, (0., -40.));
+        assert_eq!(map.get_road_index_by_key("a"), Some(lane("a", 2)));
+        assert_eq!(map.get_lane(lane("a", 2)).width, 30.);
+
+        assert_eq!(map.get_lane(lane("a", 1)).right, Some(lane("a", 2)));
+        assert_eq!(map.get_lane(lane("a", 2)).left, Some(lane("a", 1)));
+        // oncoming lane is not a lane to change into
+        assert_eq!(map.get_lane(lane("a", 1)).left, None);
+        assert_eq!(map.get_lane(lane("a", 0)).right, None);
+
+        // both lanes merge into the only one going forward, lanes going backward lead to the previous road
+        assert_eq!(map.get_next_roads(lane("a", 1)), [lane("b", 1)]);
+        assert_eq!(map.get_next_roads(lane("a", 2)), [lane("b", 1)]);
+        assert_eq!(map.get_next_roads(lane("a", 0)), [lane("d", 0)]);
+        assert_eq!(map.get_next_roads(lane("b", 0)), [lane("a", 0)]);
+
+        let toml = format!("{LANES_MAP}{}", "[cars]\nfirst = { road = 'a', lane = 1, offset = 50.0 }\n");
+        let cars = cars_from_toml(&toml, &map).expect("Should have read the cars");
+        assert_eq!(cars[0].navigator.get_road_id(), lane("a", 1));
+        assert_eq!(cars[0].position.coordinates, (50., -5.));
+        let toml = format!("{LANES_MAP}{}", "[cars]\nfirst = { road = 'b', lane = 2 }\n");
+        match cars_from_toml(&toml, &map) {
+            Err(RoadMapDeserializationError::UnknownLane { path }) => assert_eq!(path, "cars.first.lane"),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn rejects_roads_without_length() {
+        let path_of_error = |roads: &str| match road_nodes_from_toml(&format!("[roads]\n{roads}\n[nodes]\na = ['a']\n")) {
+            Err(RoadMapDeserializationError::DegenerateRoad { path }) => path,
+            other => panic!("unexpected result: {other:?}"),
+        };
+        assert_eq!(path_of_error("a = { type = 'Line', start_x = 10.0, start_y = 0.0, end_x = 10.0, end_y = 0.0 }"), "roads.a");
+        assert_eq!(path_of_error("a = { type = 'Turn', x = 0.0, y = 0.0, r = 0.0, start = '-1/2', end = '1/2', dir = 'CCW' }"), "roads.a.r");
+        // left lane of a counter-clockwise turn is closer to its center than the turn is wide
+        assert_eq!(path_of_error("a = { type = 'Turn', x = 0.0, y = 0.0, r = 15.0, start = '-1/2', end = '1/2', dir = 'CCW', lanes = [{}, {}] }"), "roads.a.lanes[0]");
+        assert!(road_nodes_from_toml("[roads]\na = { type = 'Turn', x = 0.0, y = 0.0, r = 45.0, start = '-1/2', end = '1/2', dir = 'CCW', lanes = [{}, {}] }\n[nodes]\na = ['a']\n").is_ok());
+    }
+
+    #[test]
+    fn reads_intersections() {
+        let map = road_nodes_from_toml(LANES_MAP).expect("Should have read the map");
+        let lane = |key: &str, lane: usize| map.get_lane_index_by_key(key, lane).expect("Lane should exist");
+        let toml = format!("{LANES_MAP}{}", r#"
+            [intersections]
+            corner = { plan = 'Actuated', max_green = 30.0, amber = 2.0, phases = [{ roads = ['a'] }, { roads = [{ road = 'c', lane = 0 }] }] }
+        "#);
+        let intersections = intersections_from_toml(&toml, &map).expect("Should have read the intersections");
+        let corner = &intersections[0];
+        // road key stands for every lane going its way
+        assert_eq!(corner.phases[0].roads, [lane("a", 2), lane("a", 1)]);
+        assert_eq!(corner.phases[1].roads, [lane("c", 0)]);
+        assert!(matches!(corner.plan, PhasePlan::Actuated { min_green: 5.0, max_green: 30.0, .. }));
+        assert_eq!((corner.amber, corner.all_red), (2.0, DEFAULT_ALL_RED_TIME));
+
+        let toml = format!("{LANES_MAP}{}", "[intersections]
+corner = { plan = 'Roundabout', phases = [{ roads = ['a'] }] }
+");
+        match intersections_from_toml(&toml, &map) {
+            Err(RoadMapDeserializationError::UnknownPhasePlan { path, value }) => assert_eq!((path.as_str(), value.as_str()), ("intersections.corner.plan", "Roundabout")),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn rejects_intersections_without_phases() {
+        let map = road_nodes_from_toml(LANES_MAP).expect("Should have read the map");
+        let path_of_error = |intersections: &str| match intersections_from_toml(&format!("{LANES_MAP}[intersections]\n{intersections}\n"), &map) {
+            Err(RoadMapDeserializationError::InvalidFieldType { path }) => path,
+            other => panic!("unexpected result: {other:?}"),
+        };
+        assert_eq!(path_of_error("corner = { phases = [] }"), "intersections.corner.phases");
+        assert_eq!(path_of_error("corner = { phases = [{ roads = ['a'] }, { roads = [] }] }"), "intersections.corner.phases[1].roads");
+    }
+
+    #[test]
+    fn reads_reservation_managers() {
+        let map = road_nodes_from_toml(LANES_MAP).expect("Should have read the map");
+        let lane = |key: &str, lane: usize| map.get_lane_index_by_key(key, lane).expect("Lane should exist");
+        let toml = format!("{LANES_MAP}{}", r#"
+            [reservations]
+            corner = { roads = ['b', { road = 'c', lane = 0 }], request_distance = 150.0 }
+        "#);
+        let managers = reservation_managers_from_toml(&toml, &map).expect("Should have read the reservation managers");
+        assert_eq!(managers[0].roads, [lane("b", 1), lane("c", 0)]);
+        assert_eq!(managers[0].request_distance, 150.);
+        assert_eq!(reservation_managers_from_toml(LANES_MAP, &map).map(|managers| managers.len()).ok(), Some(0));
+    }
+
+    #[test]
+    fn finds_conflict_zones_at_merges() {
+        let toml = r#"
+            [roads]
+            a = { type = 'Line', start_x = 0.0, start_y = 0.0, end_x = 200.0, end_y = 0.0 }
+            b = { type = 'Line', start_x = 200.0, start_y = -200.0, end_x = 200.0, end_y = 0.0, priority = 'Stop' }
+            c = { type = 'Line', start_x = 200.0, start_y = 0.0, end_x = 400.0, end_y = 0.0 }
+            [nodes]
+            a = ['c']
+            b = ['c']
+            c = ['a']
+        "#;
+        let map = road_nodes_from_toml(toml).expect("Should have read the map");
+        let road = |key: &str| map.get_road_index_by_key(key).expect("Road should exist");
+        assert_eq!((map.get_lane(road("a")).priority, map.get_lane(road("b")).priority), (Priority::Major, Priority::Stop));
+
+        let [zone] = map.get_conflict_zones() else { panic!("There should be one conflict zone") };
+        assert_eq!(zone.road, road("c"));
+        assert_eq!(zone.incoming, [road("a"), road("b")]);
+        // roads meet at a right angle, lanes stop overlapping a lane width before the end
+        assert_eq!(zone.get_waiting_distance(road("b")), DEFAULT_LANE_WIDTH);
+        assert_eq!(map.get_conflict_zone(road("b"), road("c")).map(|zone| zone.road), Some(road("c")));
+        assert_eq!(map.get_conflict_zone(road("c"), map.get_next_roads(road("c"))[0]).map(|zone| zone.road), None);
+
+        match road_nodes_from_toml(&toml.replace("'Stop'", "'Roundabout'")) {
+            Err(RoadMapDeserializationError::UnknownPriority { path, value }) => assert_eq!((path.as_str(), value.as_str()), ("roads.b.priority", "Roundabout")),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn builds_roundabout() {
+        let map = road_nodes_from_toml(include_str!("../resources/roundabout.toml")).expect("Sample map should be valid");
+        let road = |key: &str| map.get_road_index_by_key(key).expect("Road should exist");
+        let close = |a: (f32, f32), b: (f32, f32)| (a.0 - b.0).hypot(a.1 - b.1) < 1e-3;
+        let [roundabout] = map.get_roundabouts() else { panic!("There should be one roundabout") };
+        // every entry and every exit touches the circle at its own point
+        assert_eq!((roundabout.entries.len(), roundabout.ring.len()), (4, 8));
+        assert_eq!(map.get_lane(road("center.in.0")).priority, Priority::Yield);
+        assert_eq!(map.get_conflict_zone(road("center.in.0"), map.get_next_roads(road("center.in.0"))[0]).map(|zone| zone.incoming.len()), Some(2));
+
+        let entry = map.get_road_by_id(road("center.in.0"));
+        assert_eq!(map.get_next_roads(road("n_in")), [road("center.in.0")]);
+        assert!(close(map.get_road_by_id(road("n_in")).get_end(), entry.get_start().coordinates));
+        let ring = map.get_road_by_id(map.get_next_roads(road("center.in.0"))[0]);
+        assert!(close(entry.get_end(), ring.get_start().coordinates));
+        assert!((normalize_angle(entry.tangent_at(entry.length()) - ring.tangent_at(0.))).abs() < 1e-3);
+
+        // cars go round counter-clockwise and take the first exit, which is the one to the west
+        let exit = map.get_next_roads(road("center.in.0")).iter()
+            .flat_map(|ring| map.get_next_roads(*ring))
+            .next().copied().expect("Ring goes on");
+        assert_eq!(exit, road("center.out.3"));
+        assert!(close(map.get_road_by_id(exit).get_end(), map.get_road_by_id(road("w_out")).get_start().coordinates));
+
+        let toml = include_str!("../resources/roundabout.toml").replace("to = 'e_out'", "to = 'e_in'");
+        match road_nodes_from_toml(&toml) {
+            Err(RoadMapDeserializationError::InvalidRoundaboutArm { path }) => assert_eq!(path, "roundabouts.center.arms[1].to"),
+            other => panic!("unexpected result: {other:?}"),
+        }
+        let toml = include_str!("../resources/roundabout.toml").replace("r = 140.0", "r = 0.0");
+        match road_nodes_from_toml(&toml) {
+            Err(RoadMapDeserializationError::DegenerateRoad { path }) => assert_eq!(path, "roundabouts.center.r"),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn routes_across_lanes() {
+        let map = Rc::new(road_nodes_from_toml(LANES_MAP).expect("Should have read the map"));
+        let (left, right) = (map.get_lane_index_by_key("b", 1).unwrap(), map.get_lane_index_by_key("a", 2).unwrap());
+        let start = map.get_lane_index_by_key("a", 1).unwrap();
+        assert_eq!(map.find_route(start, right), Some(vec![start, right]));
+
+        let mut navigator = Navigator::new(Rc::clone(&map), map.get_lane_index_by_key("d", 1).unwrap()).expect("Should have created the navigator");
+        navigator.set_destination(start).expect("Should have found a route");
+        // road leads to the right lane, then car changes into the left one alongside it
+        assert_eq!(navigator.get_upcoming_road_ids().take(2).collect::<Vec<_>>(), [right, left]);
+        navigator.switch_to_next_road();
+        assert_eq!(navigator.get_road_id(), right);
+        assert_eq!(navigator.get_lane_change(), Some(start));
+        assert_eq!(navigator.get_upcoming_road_ids().next(), Some(left));
+
+        // car that did not change lanes in time goes around once more
+        let mut missed = navigator.clone();
+        missed.switch_to_next_road();
+        assert_eq!(missed.get_destination(), Some(start));
+        assert_eq!(missed.get_lane_change(), None);
+
+        navigator.change_lane(start);
+        assert_eq!(navigator.get_road_id(), start);
+        assert_eq!(navigator.get_lane_change(), None);
+    }
+
+    /// Drives a point along `turn` and past its end, checking that the navigator
+    /// switches to the next road exactly when the point leaves the turn.
+    fn assert_tracks_turn(turn: Road) {
+        let length = turn.length();
+        let points: Vec<(f32, f32)> = (0..=(length as i32 + 10)).map(|s| turn.point_at(s as f32)).collect();
+        let end = turn.get_end();
+        let tangent = turn.tangent_at(length);
+        let exit = Road::Line { start: end, end: (end.0 + 100.0 * tangent.cos(), end.1 + 100.0 * tangent.sin()) };
+        let map = RoadMap::new(vec![
+            RoadNode { road: turn, next: vec![1] },
+            RoadNode { road: exit, next: vec![0] },
+        ]).expect("Should have created RoadMap");
+        let mut navigator = Navigator::new(Rc::new(map), 0).expect("Should have created the navigator");
+
+        for (s, point) in points.into_iter().enumerate() {
+            let progress = navigator.track(point);
+            if (s as f32) < length - 0.5 {
+                assert_eq!(navigator.current_id, 0, "left the turn too early, at {s} of {length}");
+                assert!((progress - s as f32).abs() < 0.1, "progress {progress} at {s}");
+            } else if (s as f32) > length + 0.5 {
+                assert_eq!(navigator.current_id, 1, "did not leave the turn at {s} of {length}");
+                assert!(progress < 10.5);
+            }
+        }
+    }
+
+    #[test]
+    fn tracks_turn_crossing_angle_seam() {
+        use std::f32::consts::PI;
+        assert_tracks_turn(Road::Turn { coordinates: (0., 0.), radius: 50., start_angle: 3. * PI / 4., end_angle: -3. * PI / 4., direction: crate::model::RoadTurnDirection::CCW });
+        assert_tracks_turn(Road::Turn { coordinates: (0., 0.), radius: 50., start_angle: -3. * PI / 4., end_angle: 3. * PI / 4., direction: crate::model::RoadTurnDirection::CW });
+    }
+
+    #[test]
+    fn tracks_turn_longer_than_half_circle() {
+        use std::f32::consts::PI;
+        assert_tracks_turn(Road::Turn { coordinates: (10., 10.), radius: 40., start_angle: 0., end_angle: PI / 2., direction: crate::model::RoadTurnDirection::CW });
+        assert_tracks_turn(Road::Turn { coordinates: (10., 10.), radius: 40., start_angle: -PI, end_angle: -PI / 2., direction: crate::model::RoadTurnDirection::CCW });
+    }
+
+    #[test]
+    fn tracks_full_circle() {
+        assert_tracks_turn(Road::Turn { coordinates: (0., 0.), radius: 30., start_angle: 1., end_angle: 1., direction: crate::model::RoadTurnDirection::CCW });
+    }
 }
@@ -1,37 +1,239 @@
-use crate::{model::Car, util::normalize_angle};
+use crate::{model::{Car, Gear, TireModel, VehicleModel, VehicleParams, GRAVITY}, util::normalize_angle};
 
-const UPS: f32 = 60.;
+/// Default rate at which physics is updated, in updates per second
+pub const DEFAULT_UPS: f32 = 60.;
 
 pub trait Physics {
-    fn update(&mut self);
+    /// Advances the car by `dt` seconds
+    fn update(&mut self, dt: f32);
 }
 
-const EPSILON: f32 = 1.0;
-pub const BRAKES: f32 = 130.0 / UPS;
-pub const ACCELLERATION: f32 = 40.0 / UPS;
-pub const STEER_SPEED: f32 = 0.025; // 0.03 for low speed // 0.01 for high speed
-pub const MAX_STEER: f32 = 0.02;
-pub const MAX_SPEED: f32 = 200.0;
+/// Below that speed slip angles are meaningless, dynamic model drives like the kinematic one
+const DYNAMIC_MIN_SPEED: f32 = 20.0;
 
 impl Physics for Car {
-    fn update(&mut self) {
-
-        if self.brakes && self.speed.abs() > EPSILON {
-            self.speed =- BRAKES.min(self.speed.abs()) * self.speed.signum();
-        } else {
-            let speed_diff = self.desired_speed - self.speed;
-            self.speed += ACCELLERATION.min(speed_diff.abs()) * speed_diff.signum();
-            self.speed = MAX_SPEED.min(self.speed.abs()) * self.speed.signum();
+    fn update(&mut self, dt: f32) {
+        let VehicleParams { brakes, acceleration, steer_speed, max_steer, max_speed, max_reverse_speed, .. } = self.params;
+
+        // direction can only be changed standing still, so the driver brakes until then
+        if self.desired_gear != self.gear && self.speed == 0.0 {
+            self.gear = self.desired_gear;
+        }
+        let shifting = self.desired_gear != self.gear;
+
+        if self.brakes || shifting {
+            self.speed -= (brakes * dt).min(self.speed.abs()) * self.speed.signum();
+        } else if let Some(target_speed) = match self.gear {
+            Gear::Drive => Some(self.desired_speed.clamp(0.0, max_speed)),
+            Gear::Reverse => Some(-self.desired_speed.clamp(0.0, max_reverse_speed)),
+            Gear::Neutral => None,
+        } {
+            let speed_diff = target_speed - self.speed;
+            self.speed += (acceleration * dt).min(speed_diff.abs()) * speed_diff.signum();
         }
 
-        let steer_diff = self.desired_steer - self.steer;
-        self.steer += (STEER_SPEED / UPS).min(steer_diff.abs()) * steer_diff.signum();
-        self.steer = MAX_STEER.min(self.steer.abs()) * self.steer.signum();
+        match self.vehicle_model.clone() {
+            VehicleModel::Point => {
+                let steer_diff = self.desired_steer - self.steer;
+                self.steer += (steer_speed * dt).min(steer_diff.abs()) * steer_diff.signum();
+                self.steer = max_steer.min(self.steer.abs()) * self.steer.signum();
+
+                self.position.orientation += self.steer * self.speed * dt;
+                self.position.orientation = normalize_angle(self.position.orientation);
+
+                self.position.coordinates.0 += self.speed * self.position.orientation.cos() * dt;
+                self.position.coordinates.1 += self.speed * self.position.orientation.sin() * dt;
+            },
+            VehicleModel::Bicycle { wheelbase, steering_ratio } => {
+                let wheel_angle = self.turn_steering_wheel(wheelbase, steering_ratio, dt);
+                self.drive_kinematic(wheel_angle, dt);
+            },
+            VehicleModel::Dynamic { wheelbase, steering_ratio, friction, front_stiffness, rear_stiffness, tire } => {
+                let wheel_angle = self.turn_steering_wheel(wheelbase, steering_ratio, dt);
+                // slip angles are only modelled going forwards, reversing is slow anyway
+                if self.speed < DYNAMIC_MIN_SPEED {
+                    self.drive_kinematic(wheel_angle, dt);
+                    return;
+                }
+
+                // center of mass is in the middle of the wheelbase, each axle carries half of the weight
+                let half = wheelbase / 2.0;
+                let front_slip = wheel_angle - ((self.lateral_speed + half * self.yaw_rate) / self.speed).atan();
+                let rear_slip = -((self.lateral_speed - half * self.yaw_rate) / self.speed).atan();
+                let axle_grip = friction * GRAVITY / 2.0;
+                let front_force = axle_grip * tire.get_force(front_slip, front_stiffness);
+                let rear_force = axle_grip * tire.get_force(rear_slip, rear_stiffness);
 
-        self.position.orientation += self.steer * self.speed / UPS;
+                // forces are per unit of mass, yaw inertia is taken as that of two point masses on the axles
+                let lateral_acceleration = front_force * wheel_angle.cos() + rear_force - self.speed * self.yaw_rate;
+                let yaw_acceleration = (half * front_force * wheel_angle.cos() - half * rear_force) / (half * half);
+                self.lateral_speed += lateral_acceleration * dt;
+                self.yaw_rate += yaw_acceleration * dt;
+
+                self.position.orientation = normalize_angle(self.position.orientation + self.yaw_rate * dt);
+                let (sin, cos) = self.position.orientation.sin_cos();
+                self.position.coordinates.0 += (self.speed * cos - self.lateral_speed * sin) * dt;
+                self.position.coordinates.1 += (self.speed * sin + self.lateral_speed * cos) * dt;
+            },
+        }
+    }
+}
+
+impl Car {
+    /// Turns the steering wheel towards the desired steering, as fast as the driver can.
+    /// Returns the angle front wheels end up turned to.
+    fn turn_steering_wheel(&mut self, wheelbase: f32, steering_ratio: f32, dt: f32) -> f32 {
+        // steering wheel is what the driver turns, curvature follows from it
+        let steering_wheel = (self.steer * wheelbase).atan() * steering_ratio;
+        let desired_steering_wheel = (self.desired_steer * wheelbase).atan() * steering_ratio;
+        let wheel_diff = desired_steering_wheel - steering_wheel;
+        let steering_wheel = steering_wheel + (self.params.steering_wheel_speed * dt).min(wheel_diff.abs()) * wheel_diff.signum();
+        let steering_wheel = self.params.max_steering_wheel_angle.min(steering_wheel.abs()) * steering_wheel.signum();
+        let wheel_angle = steering_wheel / steering_ratio;
+        self.steer = wheel_angle.tan() / wheelbase;
+        wheel_angle
+    }
+
+    /// Kinematic bicycle: rear axle drives along `steer` curvature
+    fn drive_kinematic(&mut self, wheel_angle: f32, dt: f32) {
+        // car center is half a wheelbase in front of the rear axle, so it drifts outwards of the turn
+        let slip = (wheel_angle.tan() / 2.0).atan();
+        let direction = self.position.orientation + slip;
+        self.position.coordinates.0 += self.speed * direction.cos() * dt;
+        self.position.coordinates.1 += self.speed * direction.sin() * dt;
+
+        self.yaw_rate = self.speed * slip.cos() * self.steer;
+        self.lateral_speed = self.speed * slip.tan();
+        self.position.orientation += self.yaw_rate * dt;
         self.position.orientation = normalize_angle(self.position.orientation);
+    }
+}
+
+impl TireModel {
+    /// Sideways force at `slip` angle, as a fraction of the most tire can give
+    fn get_force(&self, slip: f32, stiffness: f32) -> f32 {
+        let x = stiffness * slip;
+        match self {
+            TireModel::Linear => x.clamp(-1.0, 1.0),
+            TireModel::Pacejka { shape, curvature } => (shape * (x - curvature * (x - x.atan())).atan()).sin(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::model::Road;
+    use crate::navigator::{Navigator, RoadMap, RoadNode};
+
+    fn car_with_model(vehicle_model: VehicleModel) -> Car {
+        let road_map = RoadMap::new(vec![
+            RoadNode { road: Road::Line { start: (0., 0.), end: (100., 0.) }, next: vec![0] },
+        ]).expect("Should have created RoadMap");
+        let mut car = Car::from_navigator(Navigator::new(Rc::new(road_map), 0).expect("Should have created the navigator"));
+        car.vehicle_model = vehicle_model;
+        car
+    }
+
+    #[test]
+    fn bicycle_turns_with_radius_of_its_wheel_angle() {
+        let (wheelbase, steering_ratio) = (24.0, 15.0);
+        let mut car = car_with_model(VehicleModel::Bicycle { wheelbase, steering_ratio });
+        let radius = 100.0;
+        car.speed = 50.0;
+        car.desired_speed = 50.0;
+        car.desired_steer = 1.0 / radius;
+
+        // let the steering wheel settle, then drive a full circle
+        for _ in 0..120 { car.update(1.0 / 60.0); }
+        assert!((car.steer - 1.0 / radius).abs() < 1e-5);
+        assert!((car.get_wheel_angle() - (wheelbase / radius).atan()).abs() < 1e-5);
+
+        let mut lowest_y = f32::INFINITY;
+        let mut highest_y = f32::NEG_INFINITY;
+        let lap_time = 2.0 * std::f32::consts::PI * radius / car.speed;
+        for _ in 0..(lap_time * 600.0) as u32 {
+            car.update(1.0 / 600.0);
+            lowest_y = lowest_y.min(car.position.coordinates.1);
+            highest_y = highest_y.max(car.position.coordinates.1);
+        }
+        // center of the car drives a slightly wider circle than the rear axle
+        let center_radius = (radius.powi(2) + (wheelbase / 2.0).powi(2)).sqrt();
+        assert!(((highest_y - lowest_y) / 2.0 - center_radius).abs() < 0.5, "{} {}", highest_y - lowest_y, center_radius);
+    }
+
+    #[test]
+    fn bicycle_steering_wheel_turns_at_limited_speed() {
+        let (wheelbase, steering_ratio) = (24.0, 15.0);
+        let mut car = car_with_model(VehicleModel::Bicycle { wheelbase, steering_ratio });
+        car.desired_steer = 1.0;
+        car.update(0.1);
+        assert!((car.get_wheel_angle() * steering_ratio - car.params.steering_wheel_speed * 0.1).abs() < 1e-5);
+        for _ in 0..100 { car.update(0.1); }
+        assert!((car.get_wheel_angle() * steering_ratio - car.params.max_steering_wheel_angle).abs() < 1e-5);
+    }
+
+    fn dynamic(front_stiffness: f32, rear_stiffness: f32) -> VehicleModel {
+        VehicleModel::Dynamic { wheelbase: 24.0, steering_ratio: 15.0, friction: 0.9, front_stiffness, rear_stiffness, tire: TireModel::Pacejka { shape: 1.9, curvature: 0.97 } }
+    }
+
+    /// Yaw rate car settles on, driving at `speed` with steering held at `steer`
+    fn settled_yaw_rate(vehicle_model: VehicleModel, speed: f32, steer: f32) -> f32 {
+        let mut car = car_with_model(vehicle_model);
+        car.speed = speed;
+        car.desired_speed = speed;
+        car.desired_steer = steer;
+        for _ in 0..6000 { car.update(1.0 / 600.0); }
+        car.yaw_rate
+    }
+
+    #[test]
+    fn dynamic_model_follows_gentle_turns_like_kinematic_one() {
+        let (speed, steer) = (60.0, 1.0 / 200.0);
+        let yaw_rate = settled_yaw_rate(dynamic(8.0, 8.0), speed, steer);
+        assert!((yaw_rate - speed * steer).abs() / (speed * steer) < 0.05, "{yaw_rate}");
+    }
+
+    #[test]
+    fn dynamic_model_can_not_exceed_friction() {
+        let speed: f32 = 150.0;
+        let max_lateral_acceleration = 0.9 * GRAVITY;
+        // asks for four times the grip there is
+        let steer = 4.0 * max_lateral_acceleration / speed.powi(2);
+        let yaw_rate = settled_yaw_rate(dynamic(8.0, 12.0), speed, steer);
+        assert!((speed * yaw_rate).abs() <= max_lateral_acceleration * 1.01, "{} > {}", speed * yaw_rate, max_lateral_acceleration);
+    }
+
+    #[test]
+    fn soft_front_tires_understeer() {
+        let (speed, steer) = (100.0, 1.0 / 150.0);
+        let neutral = settled_yaw_rate(dynamic(8.0, 8.0), speed, steer);
+        let understeering = settled_yaw_rate(dynamic(4.0, 12.0), speed, steer);
+        let oversteering = settled_yaw_rate(dynamic(12.0, 6.0), speed, steer);
+        assert!(understeering < neutral && neutral < oversteering, "{understeering} {neutral} {oversteering}");
+    }
+
+    #[test]
+    fn changes_gear_only_standing_still() {
+        let mut car = car_with_model(VehicleModel::Point);
+        car.speed = 30.0;
+        car.desired_speed = 30.0;
+        car.desired_gear = Gear::Reverse;
+
+        car.update(1.0 / 60.0);
+        assert_eq!(car.gear, Gear::Drive);
+        assert!(car.speed < 30.0 && car.speed > 0.0);
+
+        for _ in 0..60 { car.update(1.0 / 60.0); }
+        assert_eq!(car.gear, Gear::Reverse);
+        assert!(car.speed < 0.0);
 
-        self.position.coordinates.0 += self.speed * self.position.orientation.cos() / UPS;
-        self.position.coordinates.1 += self.speed * self.position.orientation.sin() / UPS;
+        // reverse is slower than driving forwards
+        car.desired_speed = 100.0;
+        for _ in 0..300 { car.update(1.0 / 60.0); }
+        assert_eq!(car.speed, -car.params.max_reverse_speed);
     }
 }
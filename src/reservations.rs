@@ -0,0 +1,224 @@
+use crate::algorithm::CREEP_SPEED;
+use crate::model::{Car, VehicleParams};
+use crate::navigator::{RoadIndex, RoadMap};
+
+/// Cars ask for a slot once they are that close to the intersection, in pixels, unless map says otherwise
+pub const DEFAULT_REQUEST_DISTANCE: f32 = 300.0;
+/// Seconds added before and after every reservation, so that cars arriving a bit early or late still pass each other
+const RESERVATION_MARGIN: f32 = 0.5;
+/// Cars driving the same road through the intersection enter and leave it at least that many seconds after each other
+const SAME_ROAD_HEADWAY: f32 = 1.0;
+/// Car that would get to its slot that many seconds later than it was given gets a new one
+const LATE_TOLERANCE: f32 = 0.5;
+/// How finely the roads are compared to find the ones that cross, in pixels along the road
+const CROSSING_STEP: f32 = 5.0;
+
+/// What a car knows about its turn to drive through an intersection
+#[derive(Debug,Clone,PartialEq)]
+pub struct Slot {
+    /// road through the intersection the car is going to drive along
+    pub road: RoadIndex,
+    /// seconds until the front of the car may get onto `road`
+    pub arrival: f32,
+}
+
+/// Time window in which a car (by its index) drives along a road through the intersection,
+/// in seconds of the time of its manager
+#[derive(Debug,Clone,PartialEq)]
+pub struct Reservation {
+    pub car: usize,
+    pub road: RoadIndex,
+    pub arrival: f32,
+    pub departure: f32,
+}
+
+/// Lets cars through an intersection without traffic lights: every car approaching it gets a reservation
+/// for the road it is going to drive through the intersection, and reservations for the roads that cross
+/// each other never overlap in time. Cars are served in the order they can get to the intersection.
+#[derive(Debug,Clone)]
+pub struct ReservationManager {
+    /// roads inside the intersection
+    pub roads: Vec<RoadIndex>,
+    pub request_distance: f32,
+    /// pairs of `roads`, by their position there, whose lanes overlap somewhere
+    crossings: Vec<Vec<bool>>,
+    reservations: Vec<Reservation>,
+    /// seconds since the manager has started
+    time: f32,
+}
+
+impl ReservationManager {
+    pub fn new(roads: Vec<RoadIndex>, map: &RoadMap) -> Self {
+        let crossings = roads.iter().map(|&a| roads.iter().map(|&b| a != b && do_cross(map, a, b)).collect()).collect();
+        Self { roads, request_distance: DEFAULT_REQUEST_DISTANCE, crossings, reservations: Vec::new(), time: 0.0 }
+    }
+
+    /// Moves the time `dt` seconds on, lets go of the reservations of `cars` that have driven through,
+    /// and books the ones that have come close
+    pub fn update(&mut self, dt: f32, cars: &[Car]) {
+        self.time += dt;
+        let approaching: Vec<Option<(RoadIndex, f32)>> = cars.iter().map(|car| self.find_road_ahead(car)).collect();
+        // car that drove through, or was held up so much it would miss its slot, books again
+        self.reservations.retain(|reservation| approaching[reservation.car].is_some_and(|(road, distance)| {
+            let car = &cars[reservation.car];
+            road == reservation.road && (distance <= 0.0 || self.time + get_earliest_arrival(car, distance).0 <= reservation.arrival + LATE_TOLERANCE)
+        }));
+
+        let mut requests: Vec<(usize, RoadIndex, f32)> = approaching.iter().enumerate()
+            .filter(|(car, _)| self.get_reservation(*car).is_none())
+            .filter_map(|(car, ahead)| ahead.map(|(road, distance)| (car, road, distance)))
+            .collect();
+        // whoever can be there first is served first
+        let arrival = |(car, _, distance): &(usize, RoadIndex, f32)| get_earliest_arrival(&cars[*car], *distance).0;
+        requests.sort_by(|a, b| arrival(a).total_cmp(&arrival(b)).then(a.0.cmp(&b.0)));
+        for (car, road, distance) in requests {
+            let reservation = self.book(car, road, &cars[car], distance);
+            self.reservations.push(reservation);
+        }
+    }
+
+    pub fn get_reservation(&self, car: usize) -> Option<&Reservation> {
+        self.reservations.iter().find(|reservation| reservation.car == car)
+    }
+
+    pub fn get_reservations(&self) -> &[Reservation] {
+        &self.reservations
+    }
+
+    /// Slot of the car (by its index) as the car sees it
+    pub fn get_slot(&self, car: usize) -> Option<Slot> {
+        self.get_reservation(car).map(|reservation| Slot { road: reservation.road, arrival: reservation.arrival - self.time })
+    }
+
+    /// Earliest reservation for `car`, `distance` pixels before `road`, that does not overlap with the ones already made
+    fn book(&self, car_index: usize, road: RoadIndex, car: &Car, distance: f32) -> Reservation {
+        let (earliest, earliest_speed) = get_earliest_arrival(car, distance.max(0.0));
+        // car already driving along the road only has the rest of it to go
+        let length = car.navigator.get_map().get_road_by_id(road).length() + car.params.length + distance.min(0.0);
+        let reservation_at = |arrival: f32| {
+            // car that is given a later slot gets there slower, and takes longer to drive through
+            let speed = if arrival > earliest { earliest_speed.min(distance / arrival) } else { earliest_speed };
+            let crossing = get_travel_time(&car.params, car.cruise_speed, speed, length).0;
+            Reservation { car: car_index, road, arrival: self.time + arrival, departure: self.time + arrival + crossing }
+        };
+
+        // slots start right away, or right after another one ends; the last one is after every other one for sure
+        let latest = self.reservations.iter().map(|other| other.departure).fold(self.time, f32::max) + 2.0 * RESERVATION_MARGIN + SAME_ROAD_HEADWAY;
+        let mut candidates: Vec<f32> = self.reservations.iter()
+            .flat_map(|other| [other.departure + 2.0 * RESERVATION_MARGIN, other.arrival + SAME_ROAD_HEADWAY, other.departure + SAME_ROAD_HEADWAY])
+            .map(|time| time - self.time)
+            .filter(|arrival| *arrival > earliest)
+            .collect();
+        candidates.sort_by(f32::total_cmp);
+        std::iter::once(earliest).chain(candidates).chain(std::iter::once((latest - self.time).max(earliest)))
+            .map(reservation_at)
+            .find(|reservation| self.reservations.iter().all(|other| !self.do_conflict(reservation, other)))
+            .expect("Nothing is booked after the last reservation")
+    }
+
+    fn do_conflict(&self, a: &Reservation, b: &Reservation) -> bool {
+        if a.road == b.road {
+            // cars may follow each other through, as long as neither catches up with the other
+            let (first, second) = if a.arrival <= b.arrival { (a, b) } else { (b, a) };
+            return second.arrival - first.arrival < SAME_ROAD_HEADWAY || second.departure - first.departure < SAME_ROAD_HEADWAY;
+        }
+        let position = |road| self.roads.iter().position(|managed| *managed == road).expect("Reservations are for the managed roads");
+        self.crossings[position(a.road)][position(b.road)]
+            && a.arrival - RESERVATION_MARGIN < b.departure + RESERVATION_MARGIN
+            && b.arrival - RESERVATION_MARGIN < a.departure + RESERVATION_MARGIN
+    }
+
+    /// First of the managed roads on the route of `car` within `request_distance`, and how far the front of the car is from it,
+    /// negative once the car is driving along it
+    fn find_road_ahead(&self, car: &Car) -> Option<(RoadIndex, f32)> {
+        let navigator = &car.navigator;
+        let front = navigator.get_progress() + car.params.length / 2.0;
+        if self.roads.contains(&navigator.get_road_id()) { return Some((navigator.get_road_id(), -front)); }
+        let map = navigator.get_map();
+        let mut distance = navigator.get_road().length() - front;
+        for road in navigator.get_upcoming_road_ids() {
+            if distance > self.request_distance { return None; }
+            if self.roads.contains(&road) { return Some((road, distance)); }
+            distance += map.get_road_by_id(road).length();
+        }
+        None
+    }
+}
+
+/// Whether lanes of roads `a` and `b` overlap somewhere
+fn do_cross(map: &RoadMap, a: RoadIndex, b: RoadIndex) -> bool {
+    let (road_a, road_b) = (map.get_road_by_id(a), map.get_road_by_id(b));
+    let clearance = (map.get_lane(a).width + map.get_lane(b).width) / 2.0;
+    let steps = (road_a.length() / CROSSING_STEP).ceil() as u32;
+    (0..=steps).any(|step| {
+        let (s, lateral) = road_b.project(road_a.point_at((step as f32 * CROSSING_STEP).min(road_a.length())));
+        (0.0..=road_b.length()).contains(&s) && lateral.abs() < clearance
+    })
+}
+
+/// Seconds until the front of `car` gets `distance` pixels ahead if it speeds up as fast as it can, and its speed there
+fn get_earliest_arrival(car: &Car, distance: f32) -> (f32, f32) {
+    get_travel_time(&car.params, car.cruise_speed, car.speed.max(0.0), distance)
+}
+
+/// Seconds it takes to drive `distance` pixels starting at `speed` and speeding up to `cruise_speed`, and the speed at the end
+fn get_travel_time(params: &VehicleParams, cruise_speed: f32, speed: f32, distance: f32) -> (f32, f32) {
+    let top_speed = cruise_speed.min(params.max_speed).max(CREEP_SPEED);
+    let speed = speed.max(CREEP_SPEED).min(top_speed);
+    let speeding_up = (top_speed.powi(2) - speed.powi(2)) / (2.0 * params.acceleration);
+    if distance <= speeding_up {
+        let end_speed = (speed.powi(2) + 2.0 * params.acceleration * distance).sqrt();
+        ((end_speed - speed) / params.acceleration, end_speed)
+    } else {
+        ((top_speed - speed) / params.acceleration + (distance - speeding_up) / top_speed, top_speed)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::model::Road;
+    use crate::navigator::{Navigator, RoadNode};
+
+    /// Two roads crossing at the origin, each with a road leading into it
+    fn crossing() -> Rc<RoadMap> {
+        Rc::new(RoadMap::new(vec![
+            RoadNode { road: Road::Line { start: (-300., 0.), end: (-40., 0.) }, next: vec![1] },
+            RoadNode { road: Road::Line { start: (-40., 0.), end: (40., 0.) }, next: vec![0] },
+            RoadNode { road: Road::Line { start: (0., -300.), end: (0., -40.) }, next: vec![3] },
+            RoadNode { road: Road::Line { start: (0., -40.), end: (0., 40.) }, next: vec![2] },
+        ]).expect("Should have created RoadMap"))
+    }
+
+    fn car_before(map: &Rc<RoadMap>, road: RoadIndex, distance: f32) -> Car {
+        let mut navigator = Navigator::new(Rc::clone(map), road).expect("Should have created the navigator");
+        navigator.set_progress(260. - distance);
+        Car::from_navigator(navigator)
+    }
+
+    #[test]
+    fn crossing_roads_are_booked_one_after_another() {
+        let map = crossing();
+        let mut manager = ReservationManager::new(vec![1, 3], &map);
+        manager.request_distance = 200.;
+        let cars = [car_before(&map, 0, 100.), car_before(&map, 2, 120.), car_before(&map, 0, 400.)];
+
+        manager.update(0.1, &cars);
+        let (first, second) = (manager.get_reservation(0).unwrap().clone(), manager.get_reservation(1).unwrap().clone());
+        // the one closer to the intersection goes first, the other one waits until it is through
+        assert!(first.arrival < second.arrival);
+        assert!(second.arrival - RESERVATION_MARGIN >= first.departure + RESERVATION_MARGIN - 1e-3, "{first:?} {second:?}");
+        // cars still far away do not ask yet
+        assert_eq!(manager.get_reservation(2), None);
+        assert!(manager.get_slot(1).is_some_and(|slot| slot.road == 3 && slot.arrival > 0.));
+
+        // car that has driven through lets go of its reservation
+        let through = Car::from_navigator(Navigator::new(Rc::clone(&map), 0).expect("Should have created the navigator"));
+        manager.update(0.1, &[through, car_before(&map, 2, 120.)]);
+        assert_eq!(manager.get_reservation(0), None);
+        assert_eq!(manager.get_reservation(1).map(|reservation| reservation.road), Some(3));
+    }
+}
@@ -0,0 +1,102 @@
+use crate::collision::bodies_collide;
+use crate::model::{NearbyCar, Position};
+
+/// Car brakes as hard as it can when it would hit something sooner than that, in seconds
+pub const EMERGENCY_BRAKING_TIME: f32 = 1.0;
+/// How finely time to collision is looked for, in seconds
+const TIME_TO_COLLISION_STEP: f32 = 1.0 / 30.0;
+
+/// Time until bodies of `a` and `b` touch, if both keep their current speed and steering.
+/// `None` when they don't touch within `horizon` seconds.
+pub fn get_time_to_collision(a: &NearbyCar, b: &NearbyCar, horizon: f32) -> Option<f32> {
+    let steps = (horizon / TIME_TO_COLLISION_STEP).ceil() as u32;
+    (0..=steps)
+        .map(|step| step as f32 * TIME_TO_COLLISION_STEP)
+        .find(|time| bodies_collide(&get_position_after(a, *time), (a.length, a.width), &get_position_after(b, *time), (b.length, b.width)))
+}
+
+/// Same as `get_time_to_collision`, but the collision also has to happen if both cars straighten their wheels.
+/// Cars coming out of a turn next to each other would otherwise look like they are about to crash.
+pub fn get_certain_time_to_collision(a: &NearbyCar, b: &NearbyCar, horizon: f32) -> Option<f32> {
+    let straight = |car: &NearbyCar| NearbyCar { steer: 0.0, ..car.clone() };
+    let keeping_steering = get_time_to_collision(a, b, horizon)?;
+    let straightening = get_time_to_collision(&straight(a), &straight(b), horizon)?;
+    Some(keeping_steering.max(straightening))
+}
+
+/// Which of `others` `car` would hit first within `EMERGENCY_BRAKING_TIME`, by its index, and the certain time to collision with it.
+/// Bodies that already touch `car` are not an emergency anymore, but a collision.
+/// Neither are bodies that close in on `car` from behind, braking would only make them hit harder.
+pub fn get_emergency(car: &NearbyCar, others: &[NearbyCar]) -> Option<(usize, f32)> {
+    others.iter().enumerate()
+        .filter(|(_, other)| !bodies_collide(&car.position, (car.length, car.width), &other.position, (other.length, other.width)))
+        .filter(|(_, other)| is_in_the_way(car, other))
+        .filter_map(|(i, other)| Some((i, get_certain_time_to_collision(car, other, EMERGENCY_BRAKING_TIME)?)))
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+}
+
+/// Whether `other` is ahead of `car`, or `car` itself drives towards it, e.g. backing up into it
+fn is_in_the_way(car: &NearbyCar, other: &NearbyCar) -> bool {
+    let (dx, dy) = (other.position.coordinates.0 - car.position.coordinates.0, other.position.coordinates.1 - car.position.coordinates.1);
+    let ahead = dx * car.position.orientation.cos() + dy * car.position.orientation.sin();
+    ahead > 0.0 || car.speed * ahead > 0.0
+}
+
+fn get_position_after(car: &NearbyCar, time: f32) -> Position {
+    let distance = car.speed * time;
+    let (x, y) = car.position.coordinates;
+    let start = car.position.orientation;
+    let end = start + car.steer * distance;
+    // going straight, arc formula would divide by zero
+    let coordinates = if (car.steer * distance).abs() < 1e-4 {
+        (x + distance * start.cos(), y + distance * start.sin())
+    } else {
+        (x + (end.sin() - start.sin()) / car.steer, y - (end.cos() - start.cos()) / car.steer)
+    };
+    Position { coordinates, orientation: end }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+
+    fn car(x: f32, y: f32, orientation: f32, speed: f32) -> NearbyCar {
+        NearbyCar { position: Position { coordinates: (x, y), orientation }, speed, steer: 0., length: 40., width: 20. }
+    }
+
+    #[test]
+    fn head_on_cars_meet_in_the_middle() {
+        // 100 px between the bumpers, closing at 100 px per second
+        let time = get_time_to_collision(&car(0., 0., 0., 50.), &car(140., 0., PI, 50.), 5.).expect("Cars should collide");
+        assert!((time - 1.0).abs() <= TIME_TO_COLLISION_STEP, "{time}");
+        assert_eq!(get_time_to_collision(&car(0., 0., 0., 50.), &car(140., 0., PI, 50.), 0.5), None);
+    }
+
+    #[test]
+    fn passing_cars_do_not_collide() {
+        assert_eq!(get_time_to_collision(&car(0., 0., 0., 100.), &car(140., 40., PI, 100.), 5.), None);
+        // following at the same speed
+        assert_eq!(get_time_to_collision(&car(0., 0., 0., 100.), &car(60., 0., 0., 100.), 5.), None);
+    }
+
+    #[test]
+    fn turning_car_misses_what_is_straight_ahead() {
+        let obstacle = car(150., 0., 0., 0.);
+        let turning = NearbyCar { steer: 1. / 50., ..car(0., 0., 0., 100.) };
+        assert_eq!(get_time_to_collision(&turning, &obstacle, 5.), None);
+        assert!(get_time_to_collision(&car(0., 0., 0., 100.), &obstacle, 5.).is_some());
+    }
+
+    #[test]
+    fn car_does_not_brake_for_faster_car_behind() {
+        let (front, behind) = (car(60., 0., 0., 50.), car(0., 0., 0., 150.));
+        // follower would hit the car in front within half a second, it is up to the follower to brake
+        assert_eq!(get_emergency(&behind, std::slice::from_ref(&front)).map(|(other, _)| other), Some(0));
+        assert_eq!(get_emergency(&front, std::slice::from_ref(&behind)), None);
+        // unless the car in front backs up into it
+        assert!(get_emergency(&car(60., 0., 0., -150.), &[car(0., 0., 0., 0.)]).is_some());
+    }
+}
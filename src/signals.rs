@@ -0,0 +1,204 @@
+use crate::model::Car;
+use crate::navigator::RoadIndex;
+
+/// Seconds of amber light after every green one, unless map says otherwise
+pub const DEFAULT_AMBER_TIME: f32 = 3.0;
+/// Seconds everybody has red light after amber, so the intersection clears before the next phase
+pub const DEFAULT_ALL_RED_TIME: f32 = 1.0;
+/// Seconds of green light of a phase in a fixed-time plan, unless map says otherwise
+pub const DEFAULT_GREEN_TIME: f32 = 10.0;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum LightState {
+    Green,
+    Amber,
+    Red,
+}
+
+/// Light shown to the cars at the end of `road`
+#[derive(Debug,Clone,PartialEq)]
+pub struct Signal {
+    pub road: RoadIndex,
+    pub state: LightState,
+}
+
+/// Roads which get green light together
+#[derive(Debug,Clone)]
+pub struct Phase {
+    pub roads: Vec<RoadIndex>,
+    /// seconds of green light in a fixed-time plan
+    pub green: f32,
+}
+
+/// How long every phase stays green
+#[derive(Debug,Clone)]
+pub enum PhasePlan {
+    /// every phase gets its own `green` time, one after another
+    FixedTime,
+    /// phase stays green for `min_green`, and then as long as cars keep coming within `detection_distance`
+    /// of its stop lines, but not longer than `max_green` after a car started waiting on another phase.
+    /// Phases nobody waits for are skipped.
+    Actuated { min_green: f32, max_green: f32, detection_distance: f32 },
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+enum Stage {
+    Green,
+    Amber,
+    AllRed,
+}
+
+/// Traffic lights at the ends of the roads coming into an intersection, switched by a phase plan
+#[derive(Debug,Clone)]
+pub struct Intersection {
+    pub phases: Vec<Phase>,
+    pub plan: PhasePlan,
+    pub amber: f32,
+    pub all_red: f32,
+    /// phase which has green light, or had it last
+    phase: usize,
+    stage: Stage,
+    /// seconds since the stage has started
+    time: f32,
+    /// seconds cars have been waiting on other phases during this green
+    waiting: f32,
+}
+
+impl Intersection {
+    /// First phase gets green light right away. There should be at least one phase.
+    pub fn new(phases: Vec<Phase>, plan: PhasePlan) -> Self {
+        Self { phases, plan, amber: DEFAULT_AMBER_TIME, all_red: DEFAULT_ALL_RED_TIME, phase: 0, stage: Stage::Green, time: 0.0, waiting: 0.0 }
+    }
+
+    /// Moves the lights `dt` seconds on. Actuated plan looks at `cars` to see who waits for green.
+    pub fn update(&mut self, dt: f32, cars: &[Car]) {
+        self.time += dt;
+        match self.stage {
+            Stage::Green => {
+                let others_wait = (0..self.phases.len()).any(|phase| phase != self.phase && self.is_demanded(phase, cars));
+                self.waiting = if others_wait { self.waiting + dt } else { 0.0 };
+                if self.is_green_over(others_wait, cars) {
+                    self.stage = Stage::Amber;
+                    self.time = 0.0;
+                }
+            },
+            Stage::Amber => if self.time >= self.amber {
+                self.stage = Stage::AllRed;
+                self.time -= self.amber;
+            },
+            Stage::AllRed => if self.time >= self.all_red {
+                self.phase = self.get_next_phase(cars);
+                self.stage = Stage::Green;
+                self.time -= self.all_red;
+                self.waiting = 0.0;
+            },
+        }
+    }
+
+    /// Light shown at the end of `road`, if it is one of the roads of this intersection
+    pub fn get_light(&self, road: RoadIndex) -> Option<LightState> {
+        if self.phases[self.phase].roads.contains(&road) {
+            return Some(match self.stage {
+                Stage::Green => LightState::Green,
+                Stage::Amber => LightState::Amber,
+                Stage::AllRed => LightState::Red,
+            });
+        }
+        self.phases.iter().any(|phase| phase.roads.contains(&road)).then_some(LightState::Red)
+    }
+
+    pub fn get_signals(&self) -> Vec<Signal> {
+        self.phases.iter()
+            .flat_map(|phase| phase.roads.iter())
+            .map(|&road| Signal { road, state: self.get_light(road).expect("Road is in one of the phases") })
+            .collect()
+    }
+
+    fn is_green_over(&self, others_wait: bool, cars: &[Car]) -> bool {
+        match self.plan {
+            PhasePlan::FixedTime => self.time >= self.phases[self.phase].green,
+            PhasePlan::Actuated { min_green, max_green, .. } => others_wait && self.time >= min_green
+                && (self.waiting >= max_green || !self.is_demanded(self.phase, cars)),
+        }
+    }
+
+    fn get_next_phase(&self, cars: &[Car]) -> usize {
+        let next = (self.phase + 1) % self.phases.len();
+        match self.plan {
+            PhasePlan::FixedTime => next,
+            PhasePlan::Actuated { .. } => (1..=self.phases.len())
+                .map(|i| (self.phase + i) % self.phases.len())
+                .find(|phase| self.is_demanded(*phase, cars))
+                .unwrap_or(next),
+        }
+    }
+
+    /// Whether some car is close to the stop line of one of the roads of `phase`
+    fn is_demanded(&self, phase: usize, cars: &[Car]) -> bool {
+        let PhasePlan::Actuated { detection_distance, .. } = self.plan else { return true };
+        cars.iter().any(|car| {
+            let navigator = &car.navigator;
+            self.phases[phase].roads.contains(&navigator.get_road_id())
+                && navigator.get_road().length() - navigator.get_progress() <= detection_distance
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::model::Road;
+    use crate::navigator::{Navigator, RoadMap, RoadNode};
+
+    fn two_phases(plan: PhasePlan) -> Intersection {
+        Intersection::new(vec![Phase { roads: vec![0], green: 5.0 }, Phase { roads: vec![1], green: 5.0 }], plan)
+    }
+
+    fn run(intersection: &mut Intersection, seconds: f32, cars: &[Car]) {
+        for _ in 0..(seconds * 10.0).round() as u32 {
+            intersection.update(0.1, cars);
+        }
+    }
+
+    #[test]
+    fn fixed_time_plan_cycles_through_phases() {
+        let mut intersection = two_phases(PhasePlan::FixedTime);
+        assert_eq!((intersection.get_light(0), intersection.get_light(1), intersection.get_light(2)), (Some(LightState::Green), Some(LightState::Red), None));
+        run(&mut intersection, 6.0, &[]);
+        assert_eq!((intersection.get_light(0), intersection.get_light(1)), (Some(LightState::Amber), Some(LightState::Red)));
+        run(&mut intersection, 2.5, &[]);
+        assert_eq!((intersection.get_light(0), intersection.get_light(1)), (Some(LightState::Red), Some(LightState::Red)));
+        run(&mut intersection, 1.0, &[]);
+        assert_eq!((intersection.get_light(0), intersection.get_light(1)), (Some(LightState::Red), Some(LightState::Green)));
+    }
+
+    #[test]
+    fn actuated_plan_gives_green_to_waiting_cars() {
+        let map = Rc::new(RoadMap::new(vec![
+            RoadNode { road: Road::Line { start: (0., 0.), end: (100., 0.) }, next: vec![1] },
+            RoadNode { road: Road::Line { start: (100., 0.), end: (0., 0.) }, next: vec![0] },
+        ]).expect("Should have created RoadMap"));
+        let car_near_stop_line = |road| {
+            let mut navigator = Navigator::new(Rc::clone(&map), road).expect("Should have created the navigator");
+            navigator.set_progress(90.);
+            Car::from_navigator(navigator)
+        };
+        let mut intersection = two_phases(PhasePlan::Actuated { min_green: 2.0, max_green: 8.0, detection_distance: 50.0 });
+
+        // nobody waits for the other phase, so green stays
+        run(&mut intersection, 20.0, &[]);
+        assert_eq!(intersection.get_light(0), Some(LightState::Green));
+        // cars keep coming on the green phase until it maxes out
+        let both = [car_near_stop_line(0), car_near_stop_line(1)];
+        run(&mut intersection, 7.5, &both);
+        assert_eq!(intersection.get_light(0), Some(LightState::Green));
+        run(&mut intersection, 5.0, &both);
+        assert_eq!(intersection.get_light(1), Some(LightState::Green));
+        // green phase is empty, so it ends after the minimal green
+        run(&mut intersection, 6.0, &[car_near_stop_line(0)]);
+        assert_eq!(intersection.get_light(0), Some(LightState::Green));
+    }
+}
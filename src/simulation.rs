@@ -0,0 +1,470 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::algorithm::Thinker;
+use crate::collision::bodies_collide;
+use crate::model::{Car, NearbyCar, Obstacle};
+use crate::navigator::{RoadMap, RoadMapDeserializationError, road_nodes_from_toml, cars_from_toml, obstacles_from_toml, intersections_from_toml, reservation_managers_from_toml};
+use crate::physics::{Physics, DEFAULT_UPS};
+use crate::safety::get_emergency;
+use crate::reservations::ReservationManager;
+use crate::signals::{Intersection, Signal};
+
+/// Cars further than that from each other do not see each other
+const NEARBY_DISTANCE: f32 = 300.0;
+/// Car never moves further than that during one physics update, fast cars
+/// get their tick split into several updates.
+const MAX_SUBSTEP_DISTANCE: f32 = 2.0;
+
+/// Something noteworthy that happened during a tick
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimulationEvent {
+    /// Bodies of two cars (by their index) started to overlap
+    Collision { tick: u64, cars: (usize, usize) },
+    /// Car (by its index) hit an obstacle (by its index)
+    ObstacleCollision { tick: u64, car: usize, obstacle: usize },
+    /// Safety layer took over the brakes of a car, because it was about to hit `other`
+    EmergencyBraking { tick: u64, car: usize, other: Body, time_to_collision: f32 },
+}
+
+/// Something on the road a car may run into, by its index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Body {
+    Car(usize),
+    Obstacle(usize),
+}
+
+/// Owns the world and advances it one tick at a time. Does not know anything
+/// about rendering, so it can be driven by a window, a script or a test.
+#[derive(Debug)]
+pub struct Simulation {
+    map: Rc<RoadMap>,
+    cars: Vec<Car>,
+    obstacles: Vec<Obstacle>,
+    intersections: Vec<Intersection>,
+    reservation_managers: Vec<ReservationManager>,
+    tick: u64,
+    /// length of a tick, in seconds
+    timestep: f32,
+    /// time that passed, but was not simulated yet
+    accumulated_time: f32,
+    /// pairs of cars that are overlapping right now, so collision is reported only once
+    colliding: HashSet<(usize, usize)>,
+    /// pairs of car and obstacle that are overlapping right now
+    hitting_obstacles: HashSet<(usize, usize)>,
+    /// cars whose brakes are held by the safety layer, so its intervention is reported only once
+    emergency_braking: HashSet<usize>,
+}
+
+impl Simulation {
+    pub fn new(map: Rc<RoadMap>, cars: Vec<Car>) -> Self {
+        Self {
+            map, cars, obstacles: Vec::new(), intersections: Vec::new(), reservation_managers: Vec::new(), tick: 0, timestep: 1.0 / DEFAULT_UPS, accumulated_time: 0.0,
+            colliding: HashSet::new(), hitting_obstacles: HashSet::new(), emergency_braking: HashSet::new(),
+        }
+    }
+
+    /// Reads the roads, the cars, the obstacles, the intersections and their reservation managers from the same map file
+    pub fn from_toml(toml_str: &str) -> Result<Self, RoadMapDeserializationError> {
+        let map = Rc::new(road_nodes_from_toml(toml_str)?);
+        let cars = cars_from_toml(toml_str, &map)?;
+        let mut simulation = Self::new(map, cars);
+        simulation.obstacles = obstacles_from_toml(toml_str)?;
+        simulation.intersections = intersections_from_toml(toml_str, &simulation.map)?;
+        simulation.reservation_managers = reservation_managers_from_toml(toml_str, &simulation.map)?;
+        Ok(simulation)
+    }
+
+    pub fn add_obstacle(&mut self, obstacle: Obstacle) {
+        self.obstacles.push(obstacle);
+    }
+
+    pub fn add_intersection(&mut self, intersection: Intersection) {
+        self.intersections.push(intersection);
+    }
+
+    pub fn add_reservation_manager(&mut self, manager: ReservationManager) {
+        self.reservation_managers.push(manager);
+    }
+
+    /// Sets how many ticks are simulated per second of simulated time
+    pub fn set_updates_per_second(&mut self, ups: f32) {
+        self.timestep = 1.0 / ups;
+    }
+
+    /// Traffic lights switch, reservation managers hand out slots, every car thinks, safety layer checks what they decided, then every car moves for one timestep.
+    pub fn step(&mut self) -> Vec<SimulationEvent> {
+        for intersection in self.intersections.iter_mut() {
+            intersection.update(self.timestep, &self.cars);
+        }
+        for manager in self.reservation_managers.iter_mut() {
+            manager.update(self.timestep, &self.cars);
+        }
+        for (i, car) in self.cars.iter_mut().enumerate() {
+            car.slot = self.reservation_managers.iter().find_map(|manager| manager.get_slot(i));
+        }
+        let signals = self.get_signals();
+        let snapshot: Vec<NearbyCar> = self.cars.iter().map(Car::as_nearby_car).collect();
+        let obstacles: Vec<NearbyCar> = self.obstacles.iter().map(Obstacle::as_nearby_car).collect();
+        let mut emergencies = Vec::new();
+        for (i, car) in self.cars.iter_mut().enumerate() {
+            // obstacles are seen just like cars that stand still
+            let (bodies, nearby_cars): (Vec<Body>, Vec<NearbyCar>) = snapshot.iter().enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(j, other)| (Body::Car(j), other))
+                .chain(obstacles.iter().enumerate().map(|(k, obstacle)| (Body::Obstacle(k), obstacle)))
+                .filter(|(_, other)| get_distance(&snapshot[i], other) < NEARBY_DISTANCE)
+                .map(|(body, other)| (body, other.clone()))
+                .unzip();
+            car.think(&nearby_cars, &signals);
+
+            match get_emergency(&snapshot[i], &nearby_cars) {
+                Some((other, time_to_collision)) => {
+                    car.brakes = true;
+                    if self.emergency_braking.insert(i) { emergencies.push((i, bodies[other], time_to_collision)); }
+                },
+                None => { self.emergency_braking.remove(&i); },
+            }
+        }
+        for car in self.cars.iter_mut() {
+            car.previous_position = car.position.clone();
+            let substeps = (car.speed.abs() * self.timestep / MAX_SUBSTEP_DISTANCE).ceil().max(1.0);
+            for _ in 0..substeps as u32 {
+                car.update(self.timestep / substeps);
+            }
+        }
+        self.tick += 1;
+
+        let mut events: Vec<SimulationEvent> = emergencies.into_iter()
+            .map(|(car, other, time_to_collision)| SimulationEvent::EmergencyBraking { tick: self.tick, car, other, time_to_collision })
+            .collect();
+        events.extend(self.detect_collisions());
+        events
+    }
+
+    pub fn run_for(&mut self, ticks: u64) -> Vec<SimulationEvent> {
+        (0..ticks).flat_map(|_| self.step()).collect()
+    }
+
+    /// Adds time that passed since the last call, it is simulated by the following `step_due` calls
+    pub fn accumulate_time(&mut self, elapsed: f32) {
+        self.accumulated_time += elapsed;
+    }
+
+    /// Makes a step if enough time was accumulated for it
+    pub fn step_due(&mut self) -> Option<Vec<SimulationEvent>> {
+        if self.accumulated_time < self.timestep { return None; }
+        self.accumulated_time -= self.timestep;
+        Some(self.step())
+    }
+
+    /// Simulates all the time that passed, rendering frame rate does not change the outcome
+    pub fn advance(&mut self, elapsed: f32) -> Vec<SimulationEvent> {
+        self.accumulate_time(elapsed);
+        std::iter::from_fn(|| self.step_due()).flatten().collect()
+    }
+
+    /// How far between the previous and the next tick the accumulated time is, from 0 to 1
+    pub fn get_interpolation_factor(&self) -> f32 {
+        self.accumulated_time / self.timestep
+    }
+
+    fn detect_collisions(&mut self) -> Vec<SimulationEvent> {
+        let mut events = Vec::new();
+        for i in 0..self.cars.len() {
+            for j in (i + 1)..self.cars.len() {
+                if bodies_collide(&self.cars[i].position, self.cars[i].params.get_size(), &self.cars[j].position, self.cars[j].params.get_size()) {
+                    if self.colliding.insert((i, j)) {
+                        events.push(SimulationEvent::Collision { tick: self.tick, cars: (i, j) });
+                    }
+                } else {
+                    self.colliding.remove(&(i, j));
+                }
+            }
+            for (k, obstacle) in self.obstacles.iter().enumerate() {
+                if bodies_collide(&self.cars[i].position, self.cars[i].params.get_size(), &obstacle.position, (obstacle.length, obstacle.width)) {
+                    if self.hitting_obstacles.insert((i, k)) {
+                        events.push(SimulationEvent::ObstacleCollision { tick: self.tick, car: i, obstacle: k });
+                    }
+                } else {
+                    self.hitting_obstacles.remove(&(i, k));
+                }
+            }
+        }
+        events
+    }
+
+    pub fn get_map(&self) -> &Rc<RoadMap> {
+        &self.map
+    }
+    pub fn get_cars(&self) -> &[Car] {
+        &self.cars
+    }
+    pub fn get_obstacles(&self) -> &[Obstacle] {
+        &self.obstacles
+    }
+    pub fn get_intersections(&self) -> &[Intersection] {
+        &self.intersections
+    }
+    pub fn get_reservation_managers(&self) -> &[ReservationManager] {
+        &self.reservation_managers
+    }
+    /// Lights shown at the ends of the roads of all intersections right now
+    pub fn get_signals(&self) -> Vec<Signal> {
+        self.intersections.iter().flat_map(Intersection::get_signals).collect()
+    }
+    pub fn get_tick(&self) -> u64 {
+        self.tick
+    }
+    pub fn get_timestep(&self) -> f32 {
+        self.timestep
+    }
+}
+
+fn get_distance(a: &NearbyCar, b: &NearbyCar) -> f32 {
+    ((a.position.coordinates.0 - b.position.coordinates.0).powi(2) +
+     (a.position.coordinates.1 - b.position.coordinates.1).powi(2)).sqrt()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::navigator::{Navigator, RoadIndex};
+
+    #[test]
+    fn cars_stay_on_sample_map_without_window() {
+        let mut simulation = Simulation::from_toml(include_str!("../resources/map.toml")).expect("Sample map should be valid");
+        assert_eq!(simulation.get_cars().len(), 2);
+
+        let events = simulation.run_for(60 * 30);
+
+        assert_eq!(simulation.get_tick(), 60 * 30);
+        assert_eq!(events, vec![]);
+        for car in simulation.get_cars() {
+            let (x, y) = car.position.coordinates;
+            assert!(x.abs() < 300.0 && y.abs() < 300.0, "car left the map: {:?}", car.position);
+        }
+    }
+
+    #[test]
+    fn reports_collision_once() {
+        let map = Rc::new(road_nodes_from_toml(include_str!("../resources/map.toml")).expect("Sample map should be valid"));
+        let cars = vec![
+            Car::from_navigator(Navigator::new(Rc::clone(&map), 0).expect("Should have created the navigator")),
+            Car::from_navigator(Navigator::new(Rc::clone(&map), 0).expect("Should have created the navigator")),
+        ];
+        let mut simulation = Simulation::new(map, cars);
+
+        let events = simulation.run_for(10);
+
+        assert_eq!(events, vec![SimulationEvent::Collision { tick: 1, cars: (0, 1) }]);
+    }
+
+    #[test]
+    fn outcome_does_not_depend_on_frame_rate() {
+        let run = |frame_time: f32| {
+            let mut simulation = Simulation::from_toml(include_str!("../resources/map.toml")).expect("Sample map should be valid");
+            simulation.set_updates_per_second(64.0);
+            for _ in 0..(10.0 / frame_time) as u32 {
+                simulation.advance(frame_time);
+            }
+            assert_eq!(simulation.get_tick(), 640);
+            simulation.get_cars().iter().map(|car| car.position.coordinates).collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(1.0 / 32.0), run(1.0 / 128.0));
+    }
+
+    #[test]
+    fn follower_keeps_time_headway() {
+        let sample_map = include_str!("../resources/map.toml");
+        let roads = &sample_map[..sample_map.find("[cars]").expect("Sample map has cars")];
+        let toml = format!("{roads}[cars]\n0 = {{ road = 0, offset = 150.0, cruise_speed = 60.0 }}\n1 = {{ road = 0 }}\n");
+        let mut simulation = Simulation::from_toml(&toml).expect("Map should be valid");
+
+        let mut events = Vec::new();
+        let mut smallest_gap = f32::INFINITY;
+        for _ in 0..60 * 40 {
+            events.extend(simulation.step());
+            if let Some(gap) = simulation.get_cars()[1].debug.gap { smallest_gap = smallest_gap.min(gap); }
+        }
+
+        assert_eq!(events, vec![]);
+        let [leader, follower] = simulation.get_cars() else { panic!("There should be two cars") };
+        assert!(smallest_gap > 30.0, "{smallest_gap}");
+        assert!((follower.speed - leader.speed).abs() < 5.0, "{} {}", follower.speed, leader.speed);
+        let gap = follower.debug.gap.expect("Follower should see the leader");
+        assert!((gap - leader.speed * 1.5).abs() < 25.0, "{gap}");
+    }
+
+    #[test]
+    fn cars_take_turns_at_traffic_lights() {
+        let mut simulation = Simulation::from_toml(include_str!("../resources/intersection.toml")).expect("Sample map should be valid");
+        let east = simulation.get_map().get_road_index_by_key("e_in").unwrap();
+        let crossings: Vec<RoadIndex> = ["ns", "sn", "ew", "we"].iter().map(|key| simulation.get_map().get_road_index_by_key(key).unwrap()).collect();
+
+        let mut events = Vec::new();
+        let mut waited_at_red = false;
+        let mut crossed = HashSet::new();
+        for _ in 0..60 * 30 {
+            events.extend(simulation.step());
+            // car coming from the east starts at red light
+            let car = &simulation.get_cars()[1];
+            waited_at_red |= car.navigator.get_road_id() == east && car.speed < 1.0;
+            for (i, car) in simulation.get_cars().iter().enumerate() {
+                if crossings.contains(&car.navigator.get_road_id()) { crossed.insert(i); }
+            }
+        }
+
+        assert_eq!(events, vec![]);
+        assert!(waited_at_red);
+        assert_eq!(crossed.len(), simulation.get_cars().len());
+    }
+
+    #[test]
+    fn reservations_let_more_cars_through_than_traffic_lights() {
+        let throughput = |toml: &str| {
+            let mut simulation = Simulation::from_toml(toml).expect("Sample map should be valid");
+            let crossings: Vec<RoadIndex> = ["ns", "sn", "ew", "we"].iter().map(|key| simulation.get_map().get_road_index_by_key(key).unwrap()).collect();
+            let mut events = Vec::new();
+            let mut crossing = vec![false; simulation.get_cars().len()];
+            let mut crossed = 0;
+            for _ in 0..60 * 60 {
+                events.extend(simulation.step());
+                for (was_crossing, car) in crossing.iter_mut().zip(simulation.get_cars()) {
+                    let is_crossing = crossings.contains(&car.navigator.get_road_id());
+                    if is_crossing && !*was_crossing { crossed += 1; }
+                    *was_crossing = is_crossing;
+                }
+            }
+            assert_eq!(events, vec![]);
+            crossed
+        };
+
+        // same roads and cars, but nobody waits for a green light on an empty intersection
+        let (lights, reservations) = (throughput(include_str!("../resources/intersection.toml")), throughput(include_str!("../resources/reservations.toml")));
+        assert!(reservations > lights, "{reservations} {lights}");
+    }
+
+    #[test]
+    fn cars_take_turns_on_roundabout() {
+        let mut simulation = Simulation::from_toml(include_str!("../resources/roundabout.toml")).expect("Sample map should be valid");
+        let roundabout = simulation.get_map().get_roundabouts()[0].clone();
+        // cars wait a bit before the entries, where they keep clear of the ring
+        let is_close = |car: &Car| {
+            let (x, y) = car.position.coordinates;
+            (x - roundabout.coordinates.0).hypot(y - roundabout.coordinates.1) < roundabout.radius * 2.0
+        };
+
+        let mut events = Vec::new();
+        let mut gave_way = false;
+        for _ in 0..30 * 30 {
+            events.extend(simulation.step());
+            gave_way |= simulation.get_cars().iter()
+                .any(|car| is_close(car) && !roundabout.ring.contains(&car.navigator.get_road_id()) && car.speed < 10.0);
+        }
+
+        assert_eq!(events, vec![]);
+        assert!(gave_way);
+        // everybody got to where they were going
+        assert!(simulation.get_cars().iter().all(|car| car.navigator.get_destination().is_none()));
+    }
+
+    #[test]
+    fn minor_road_gives_way_at_merge() {
+        let merge = |major: &str, minor: &str| {
+            let toml = format!(r#"
+                [roads]
+                a = {{ type = 'Line', start_x = 0.0, start_y = 0.0, end_x = 600.0, end_y = 0.0, priority = '{major}' }}
+                m = {{ type = 'Line', start_x = 400.0, start_y = -400.0, end_x = 400.0, end_y = -200.0 }}
+                n = {{ type = 'Turn', x = 600.0, y = -200.0, r = 200.0, start = "1/1", end = "1/2", dir = 'CW', priority = '{minor}' }}
+                b = {{ type = 'Line', start_x = 600.0, start_y = 0.0, end_x = 1200.0, end_y = 0.0 }}
+                c = {{ type = 'Turn', x = 1200.0, y = 200.0, r = 200.0, start = "-1/2", end = "1/2", dir = 'CCW' }}
+                d = {{ type = 'Line', start_x = 1200.0, start_y = 400.0, end_x = 0.0, end_y = 400.0 }}
+                e = {{ type = 'Turn', x = 0.0, y = 200.0, r = 200.0, start = "1/2", end = "3/2", dir = 'CCW' }}
+                [nodes]
+                a = ['b']
+                m = ['n']
+                n = ['b']
+                b = ['c']
+                c = ['d']
+                d = ['e']
+                e = ['a']
+                [cars]
+                0 = {{ road = 'a', offset = 200.0 }}
+                1 = {{ road = 'm', offset = 100.0 }}
+            "#);
+            let mut simulation = Simulation::from_toml(&toml).expect("Map should be valid");
+            assert_eq!(simulation.get_map().get_conflict_zones().len(), 1);
+            let merged = simulation.get_map().get_road_index_by_key("b").unwrap();
+
+            let mut events = Vec::new();
+            let mut order = Vec::new();
+            for _ in 0..60 * 10 {
+                events.extend(simulation.step());
+                for (i, car) in simulation.get_cars().iter().enumerate() {
+                    if car.navigator.get_road_id() == merged && !order.contains(&i) { order.push(i); }
+                }
+            }
+            assert_eq!(events, vec![]);
+            order
+        };
+
+        // both arrive at about the same time, the one on the major road goes first
+        assert_eq!(merge("Major", "Minor"), [0, 1]);
+        assert_eq!(merge("Minor", "Major"), [1, 0]);
+    }
+
+    #[test]
+    fn overtakes_slow_car() {
+        let lanes = "lanes = [{}, {}]";
+        let toml = format!(r#"
+            [roads]
+            a = {{ type = 'Line', start_x = 0.0, start_y = 0.0, end_x = 600.0, end_y = 0.0, {lanes} }}
+            b = {{ type = 'Turn', x = 600.0, y = 150.0, r = 150.0, start = "-1/2", end = "1/2", dir = 'CCW', {lanes} }}
+            c = {{ type = 'Line', start_x = 600.0, start_y = 300.0, end_x = 0.0, end_y = 300.0, {lanes} }}
+            d = {{ type = 'Turn', x = 0.0, y = 150.0, r = 150.0, start = "1/2", end = "-1/2", dir = 'CCW', {lanes} }}
+            [nodes]
+            a = ['b']
+            b = ['c']
+            c = ['d']
+            d = ['a']
+            [cars]
+            0 = {{ road = 'a', offset = 200.0, cruise_speed = 60.0 }}
+            1 = {{ road = 'a' }}
+        "#);
+        let mut simulation = Simulation::from_toml(&toml).expect("Map should be valid");
+        let right_lanes: Vec<RoadIndex> = ["a", "b", "c", "d"].iter().map(|key| simulation.get_map().get_road_index_by_key(key).unwrap()).collect();
+
+        let mut events = Vec::new();
+        let mut distances = [0.0, 0.0];
+        let mut changed_lanes = false;
+        for _ in 0..60 * 20 {
+            events.extend(simulation.step());
+            for (distance, car) in distances.iter_mut().zip(simulation.get_cars()) { *distance += car.speed / 60.0; }
+            changed_lanes |= !right_lanes.contains(&simulation.get_cars()[1].navigator.get_road_id());
+        }
+
+        assert_eq!(events, vec![]);
+        assert!(changed_lanes);
+        // fast car is ahead now, back on the right lane
+        assert!(distances[1] > distances[0] + 200.0, "{distances:?}");
+        assert!(right_lanes.contains(&simulation.get_cars()[1].navigator.get_road_id()));
+    }
+
+    #[test]
+    fn brakes_in_front_of_obstacle() {
+        let sample_map = include_str!("../resources/map.toml");
+        let roads = &sample_map[..sample_map.find("[cars]").expect("Sample map has cars")];
+        // obstacle is too close for the driver to stop in time by the usual means
+        let toml = format!("{roads}[cars]\n0 = {{ road = 0, speed = 100.0 }}\n[obstacles]\n0 = {{ x = 20.0, y = -20.0 }}\n");
+        let mut simulation = Simulation::from_toml(&toml).expect("Map should be valid");
+        assert_eq!(simulation.get_obstacles().len(), 1);
+
+        let events = simulation.run_for(60 * 5);
+
+        assert!(matches!(events.as_slice(), [SimulationEvent::EmergencyBraking { car: 0, other: Body::Obstacle(0), .. }]), "{events:?}");
+        assert!(simulation.get_cars()[0].speed < 1.0, "{}", simulation.get_cars()[0].speed);
+    }
+}
@@ -0,0 +1,75 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::simulation::{Body, Simulation, SimulationEvent};
+
+/// Writes state of every car on every tick, and every event, as CSV files
+/// into a directory, so batch runs can be analyzed afterwards.
+pub struct TelemetryWriter {
+    cars: BufWriter<File>,
+    events: BufWriter<File>,
+}
+
+impl TelemetryWriter {
+    /// Creates the directory if needed and overwrites `cars.csv` and `events.csv` in it
+    pub fn create(directory: &Path) -> io::Result<Self> {
+        fs::create_dir_all(directory)?;
+        let mut cars = BufWriter::new(File::create(directory.join("cars.csv"))?);
+        let mut events = BufWriter::new(File::create(directory.join("events.csv"))?);
+        writeln!(cars, "tick,car,x,y,orientation,speed,steer,brakes,gear")?;
+        writeln!(events, "tick,event,cars,obstacle,time_to_collision")?;
+        Ok(Self { cars, events })
+    }
+
+    /// Records the current state of `simulation` and events of the last tick
+    pub fn record(&mut self, simulation: &Simulation, events: &[SimulationEvent]) -> io::Result<()> {
+        let tick = simulation.get_tick();
+        for (i, car) in simulation.get_cars().iter().enumerate() {
+            writeln!(self.cars, "{},{},{},{},{},{},{},{},{:?}", tick, i,
+                     car.position.coordinates.0, car.position.coordinates.1, car.position.orientation,
+                     car.speed, car.steer, car.brakes, car.gear)?;
+        }
+        for event in events {
+            match event {
+                SimulationEvent::Collision { tick, cars: (a, b) } => writeln!(self.events, "{},collision,{} {},,", tick, a, b)?,
+                SimulationEvent::ObstacleCollision { tick, car, obstacle } => writeln!(self.events, "{},obstacle_collision,{},{},", tick, car, obstacle)?,
+                SimulationEvent::EmergencyBraking { tick, car, other: Body::Car(other), time_to_collision } =>
+                    writeln!(self.events, "{},emergency_braking,{} {},,{}", tick, car, other, time_to_collision)?,
+                SimulationEvent::EmergencyBraking { tick, car, other: Body::Obstacle(obstacle), time_to_collision } =>
+                    writeln!(self.events, "{},emergency_braking,{},{},{}", tick, car, obstacle, time_to_collision)?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.cars.flush()?;
+        self.events.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_details_of_events() {
+        let directory = std::env::temp_dir().join(format!("driving-algorithm-telemetry-{}", std::process::id()));
+        let simulation = Simulation::from_toml(include_str!("../resources/map.toml")).expect("Sample map should be valid");
+        let mut telemetry = TelemetryWriter::create(&directory).expect("Should have created telemetry files");
+
+        telemetry.record(&simulation, &[
+            SimulationEvent::Collision { tick: 1, cars: (0, 1) },
+            SimulationEvent::ObstacleCollision { tick: 2, car: 1, obstacle: 3 },
+            SimulationEvent::EmergencyBraking { tick: 3, car: 0, other: Body::Obstacle(2), time_to_collision: 0.5 },
+            SimulationEvent::EmergencyBraking { tick: 4, car: 1, other: Body::Car(0), time_to_collision: 0.25 },
+        ]).expect("Should have recorded the events");
+        telemetry.flush().expect("Should have written the files");
+
+        let events = fs::read_to_string(directory.join("events.csv")).expect("Should have read the events");
+        fs::remove_dir_all(&directory).expect("Should have removed the files");
+        assert_eq!(events, "tick,event,cars,obstacle,time_to_collision\n1,collision,0 1,,\n2,obstacle_collision,1,3,\n3,emergency_braking,0,2,0.5\n4,emergency_braking,1 0,,0.25\n");
+    }
+}